@@ -1,49 +1,178 @@
 use {
+    serde_json::json,
     std::{
+        collections::BTreeMap,
         env,
-        fs::File,
-        io::{self, Read, Write},
-        path::PathBuf,
+        fs::{self, File, OpenOptions},
+        io::{self, Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime, UNIX_EPOCH},
     },
     util::{
-        extract_zip, list_zip_contents, zip_app_dir, OnexFile, ProjfsProvider, ReadSeek, Result,
+        app_dir_entries, compression_method_name, extract_entries, format_timestamp, from_hex,
+        generate_keypair, instances_dir, list_entries, open_entry, orphaned_instances,
+        remove_orphaned_instance, to_hex, write_app_dir, AppCache, ArchiveEntry, ArchiveEntryKind,
+        ArchiveOptions, CacheEntry, CacheRecord, ElfHeader, ExtractOptions, Manifest, OnexFile,
+        OnexWriter, PeHeaders, Result, SectionId, WorkingDir, LEGACY_ENTRY_FILE,
     },
     zip::ZipArchive,
 };
 
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(windows)]
+use util::{ProjfsProvider, ReadSeek};
+
+/// How `package_app` should describe the app it packs.
+#[derive(Default)]
+pub struct PackOptions {
+    /// A TOML manifest to embed.
+    pub manifest_path: Option<PathBuf>,
+    /// Overrides the manifest's entry, or creates a manifest if there isn't one.
+    pub entry: Option<String>,
+    /// Replaces the manifest's default arguments, if not empty. This and the
+    /// options after it need a manifest, which is made from the app's
+    /// `onex_run` file if neither a manifest nor an entry is given.
+    pub args: Vec<String>,
+    /// Variables to set for the entry, as `<name>=<value>`, on top of the
+    /// manifest's.
+    pub env: Vec<String>,
+    /// Overrides the manifest's working directory.
+    pub working_dir: Option<WorkingDir>,
+    pub digest_loader: bool,
+    /// Have the loader run the app from the user's extraction cache.
+    pub cache: bool,
+    pub archive: ArchiveOptions,
+    /// Print the files that would be packed instead of packing them.
+    pub dry_run: bool,
+    /// Say which loader is used.
+    pub verbose: bool,
+}
+
 pub fn package_app(
     app_dir: PathBuf,
     output_path: PathBuf,
     loader_path: Option<PathBuf>,
-    architecture: String,
+    architecture: Option<String>,
+    mut options: PackOptions,
 ) -> Result<()> {
-    let loader_path = get_loader_bytes(loader_path, architecture)?;
+    // Packing into the app's own directory mustn't pack the app into itself.
+    options.archive.skip_files.push(output_path.clone());
+    if options.dry_run {
+        for entry in app_dir_entries(&app_dir, &options.archive)? {
+            if !entry.is_dir() {
+                println!("{}", entry.name);
+            }
+        }
+        return Ok(());
+    }
+
+    let loader_path = get_loader_bytes(loader_path, architecture, options.verbose)?;
     let mut loader_file = File::open(&loader_path)?;
-    let mut loader_bytes = Vec::new();
-    loader_file.read_to_end(&mut loader_bytes)?;
+    let PackOptions {
+        manifest_path,
+        entry,
+        args,
+        env,
+        working_dir,
+        digest_loader,
+        cache,
+        archive,
+        ..
+    } = options;
 
-    let app_dir_bytes = zip_app_dir(&app_dir)?;
-    let output = OnexFile::generate_bytes(loader_bytes, app_dir_bytes);
+    let mut manifest = match manifest_path {
+        Some(manifest_path) => Some(Manifest::from_toml(&fs::read_to_string(&manifest_path)?)?),
+        None => None,
+    };
+    if let Some(entry) = entry {
+        manifest.get_or_insert_with(Default::default).entry = entry;
+    }
 
-    let mut output_file = File::create(&output_path)?;
-    output_file.write_all(&output)?;
+    // Anything else is applied on top of the manifest, which for apps that
+    // only have an onex_run file is made from that.
+    let overridden = !args.is_empty() || !env.is_empty() || working_dir.is_some() || cache;
+    if overridden && manifest.is_none() {
+        let legacy_entry_file = app_dir.join(LEGACY_ENTRY_FILE);
+        if !legacy_entry_file.is_file() {
+            return Err(format!(
+                "--arg, --env, --working-dir and --cache need a manifest, given with --manifest or --entry, or an {} file.",
+                LEGACY_ENTRY_FILE
+            )
+            .into());
+        }
+        manifest = Some(Manifest::from_legacy_entry_file(&fs::read_to_string(
+            &legacy_entry_file,
+        )?)?);
+    }
+    if let Some(manifest) = &mut manifest {
+        if !args.is_empty() {
+            manifest.args = args;
+        }
+        for var in env {
+            match var.find('=') {
+                Some(i) => manifest
+                    .env
+                    .insert(var[..i].to_owned(), var[i + 1..].to_owned()),
+                None => return Err(format!("'{}' is not in the form <name>=<value>.", var).into()),
+            };
+        }
+        if let Some(working_dir) = working_dir {
+            manifest.working_dir = working_dir;
+        }
+        if cache {
+            manifest.cache = true;
+        }
+        manifest.normalize_entry();
+        manifest.validate()?;
+    }
 
-    Ok(())
+    let output_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&output_path)?;
+    let result = (|| {
+        let mut writer = OnexWriter::new(output_file, &mut loader_file, digest_loader)?;
+        let entry = manifest.as_ref().map(|m| Path::new(&m.entry));
+        writer.write_section(SectionId::Payload, |w| {
+            write_app_dir(&app_dir, entry, &archive, w)?;
+            Ok(())
+        })?;
+        if let Some(manifest) = &manifest {
+            writer.add_section(SectionId::Manifest, &mut manifest.to_toml()?.as_bytes())?;
+        }
+        writer.finish()?;
+        Ok(())
+    })();
+
+    // Don't leave a half-written app behind.
+    if result.is_err() {
+        let _ = fs::remove_file(&output_path);
+    }
+    result
 }
 
 pub fn swap_app_loader(
     app_path: PathBuf,
     loader_path: Option<PathBuf>,
     output_path: Option<PathBuf>,
-    architecture: String,
+    architecture: Option<String>,
+    digest_loader: bool,
+    verbose: bool,
 ) -> Result<()> {
-    let loader_path = get_loader_bytes(loader_path, architecture)?;
+    let loader_path = get_loader_bytes(loader_path, architecture, verbose)?;
     let mut loader_file = File::open(&loader_path)?;
     let mut loader_bytes = Vec::new();
     loader_file.read_to_end(&mut loader_bytes)?;
 
     let mut onex_file = OnexFile::new(File::open(&app_path)?)?;
-    let output = OnexFile::generate_bytes(loader_bytes, onex_file.data()?);
+    let output = OnexFile::generate_sections_bytes(
+        loader_bytes,
+        onex_file.section_contents()?,
+        digest_loader,
+    )?;
 
     let output_path = output_path.unwrap_or(app_path);
     let mut output_file = File::create(&output_path)?;
@@ -52,15 +181,409 @@ pub fn swap_app_loader(
     Ok(())
 }
 
-pub fn list_app_contents(app_path: PathBuf) -> Result<()> {
+/// How `list_app_contents` prints an app's entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListFormat {
+    /// One path per line.
+    Text,
+    /// One entry per line, with everything that's known about it.
+    Long,
+    /// The paths drawn as a tree.
+    Tree,
+    /// An array of objects, one per entry.
+    Json,
+    /// A header row, then one row per entry.
+    Csv,
+}
+
+pub fn list_app_contents(app_path: PathBuf, format: ListFormat) -> Result<()> {
+    let mut onex_file = OnexFile::new(File::open(&app_path)?)?;
+    let entries = list_entries(onex_file.data_accessor()?)?;
+
+    match format {
+        ListFormat::Text => {
+            for entry in &entries {
+                println!("{}", entry.path);
+            }
+        }
+        ListFormat::Long => {
+            for entry in &entries {
+                let target = match &entry.kind {
+                    ArchiveEntryKind::Symlink(target) => format!(" -> {}", target),
+                    _ => String::new(),
+                };
+                println!(
+                    "{} {:>10} {:>10} {:<7} {:08x} {} {}{}",
+                    mode_string(entry),
+                    entry.size,
+                    entry.compressed_size,
+                    compression_method_name(entry.compression),
+                    entry.crc32,
+                    format_timestamp(entry.modified),
+                    entry.path,
+                    target
+                );
+            }
+        }
+        ListFormat::Tree => print_tree(&entries),
+        ListFormat::Json => {
+            let entries = entries
+                .iter()
+                .map(|entry| {
+                    json!({
+                        "path": entry.path,
+                        "kind": kind_name(&entry.kind),
+                        "size": entry.size,
+                        "compressed_size": entry.compressed_size,
+                        "method": compression_method_name(entry.compression),
+                        "crc32": entry.crc32,
+                        "modified": entry.modified,
+                        "mode": entry.mode.map(|mode| format!("{:o}", mode)),
+                        "target": symlink_target(entry),
+                    })
+                })
+                .collect::<Vec<_>>();
+            let json = serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())?;
+            println!("{}", json);
+        }
+        ListFormat::Csv => {
+            println!("path,kind,size,compressed_size,method,crc32,modified,mode,target");
+            for entry in &entries {
+                println!(
+                    "{},{},{},{},{},{},{},{},{}",
+                    csv_field(&entry.path),
+                    kind_name(&entry.kind),
+                    entry.size,
+                    entry.compressed_size,
+                    compression_method_name(entry.compression),
+                    entry.crc32,
+                    entry.modified,
+                    entry
+                        .mode
+                        .map(|mode| format!("{:o}", mode))
+                        .unwrap_or_default(),
+                    csv_field(symlink_target(entry).unwrap_or_default())
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn extract_app_contents(
+    app_path: PathBuf,
+    output_path: PathBuf,
+    options: ExtractOptions,
+) -> Result<()> {
     let mut onex_file = OnexFile::new(File::open(&app_path)?)?;
-    list_zip_contents(onex_file.data_accessor()?)?;
+    extract_entries(onex_file.data_accessor()?, &output_path, &options)?;
     Ok(())
 }
 
-pub fn extract_app_contents(app_path: PathBuf, output_path: PathBuf) -> Result<()> {
+pub fn cat_app_file(app_path: PathBuf, path: String) -> Result<()> {
     let mut onex_file = OnexFile::new(File::open(&app_path)?)?;
-    extract_zip(onex_file.data_accessor()?, &output_path)?;
+    let mut archive = ZipArchive::new(onex_file.data_accessor()?)?;
+    let mut entry = open_entry(&mut archive, &path)?;
+
+    let stdout = io::stdout();
+    match io::copy(&mut entry, &mut stdout.lock()) {
+        // Whatever we're piped into has seen all it wanted.
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => {
+            result?;
+            Ok(())
+        }
+    }
+}
+
+/// How `app_info` prints what it finds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InfoFormat {
+    Text,
+    Json,
+}
+
+pub fn app_info(app_path: PathBuf, format: InfoFormat) -> Result<()> {
+    let file_length = fs::metadata(&app_path)?.len();
+    let loader_target = LoaderTarget::read(&mut File::open(&app_path)?);
+    let mut onex_file = OnexFile::new(File::open(&app_path)?)?;
+    let trailer = *onex_file.trailer();
+    let sections = onex_file.sections().to_vec();
+    let data_offset = onex_file.data_offset()?;
+    let entries = list_entries(onex_file.data_accessor()?)?;
+    let manifest = onex_file.manifest();
+    let signed = sections
+        .iter()
+        .any(|section| section.id == SectionId::Signature);
+
+    let count = |kind: fn(&ArchiveEntryKind) -> bool| {
+        entries.iter().filter(|entry| kind(&entry.kind)).count()
+    };
+    let files = count(|kind| *kind == ArchiveEntryKind::File);
+    let directories = count(|kind| *kind == ArchiveEntryKind::Directory);
+    let symlinks = count(|kind| matches!(kind, ArchiveEntryKind::Symlink(_)));
+    let size: u64 = entries.iter().map(|entry| entry.size).sum();
+    let compressed_size: u64 = entries.iter().map(|entry| entry.compressed_size).sum();
+
+    match format {
+        InfoFormat::Text => {
+            let field =
+                |label: &str, value: String| println!("{:<16}{}", format!("{}:", label), value);
+            field("Format version", trailer.format_version.to_string());
+            field("File size", format!("{} bytes", file_length));
+            field(
+                "Loader",
+                format!(
+                    "{} bytes, {}, {}",
+                    data_offset,
+                    match &loader_target {
+                        Some(target) => target.arch.clone(),
+                        None => "not a Windows or Linux executable".to_owned(),
+                    },
+                    if trailer.loader_digest.is_some() {
+                        "digest recorded"
+                    } else {
+                        "no digest recorded"
+                    }
+                ),
+            );
+            println!("Sections:");
+            for section in &sections {
+                println!(
+                    "  {:<14}{} bytes at {}",
+                    section.id.to_string(),
+                    section.length,
+                    section.offset
+                );
+            }
+            field(
+                "Payload",
+                format!(
+                    "{} files, {} directories, {} symlinks, {} bytes ({} compressed)",
+                    files, directories, symlinks, size, compressed_size
+                ),
+            );
+            match &manifest {
+                Ok(manifest) => {
+                    if let Some(name) = &manifest.name {
+                        field("Name", name.clone());
+                    }
+                    if let Some(version) = &manifest.version {
+                        field("Version", version.clone());
+                    }
+                    field("Entry", manifest.entry.clone());
+                    if !manifest.args.is_empty() {
+                        field("Arguments", manifest.args.join(" "));
+                    }
+                    field(
+                        "Working dir",
+                        match manifest.working_dir {
+                            WorkingDir::Caller => "caller",
+                            WorkingDir::AppRoot => "app root",
+                        }
+                        .to_owned(),
+                    );
+                    if manifest.cache {
+                        field("Cache", "yes".to_owned());
+                    }
+                    for (key, value) in &manifest.env {
+                        field("Environment", format!("{}={}", key, value));
+                    }
+                }
+                Err(err) => field("Entry", format!("unknown ({})", err)),
+            }
+            field("Signed", if signed { "yes" } else { "no" }.to_owned());
+        }
+        InfoFormat::Json => {
+            let info = json!({
+                "format_version": trailer.format_version,
+                "file_size": file_length,
+                "loader": {
+                    "length": data_offset,
+                    "machine": loader_target.map(|target| target.arch),
+                    "digest": trailer.loader_digest.map(|digest| to_hex(&digest)),
+                },
+                "sections": sections
+                    .iter()
+                    .map(|section| json!({
+                        "id": section.id.to_string(),
+                        "offset": section.offset,
+                        "length": section.length,
+                        "digest": section.digest.map(|digest| to_hex(&digest)),
+                    }))
+                    .collect::<Vec<_>>(),
+                "payload": {
+                    "files": files,
+                    "directories": directories,
+                    "symlinks": symlinks,
+                    "size": size,
+                    "compressed_size": compressed_size,
+                },
+                "manifest": manifest.as_ref().ok(),
+                "manifest_error": manifest.as_ref().err().map(|err| err.to_string()),
+                "signed": signed,
+            });
+            let json = serde_json::to_string_pretty(&info).map_err(|e| e.to_string())?;
+            println!("{}", json);
+        }
+    }
+
+    Ok(())
+}
+
+/// How many characters of a cache key to show, which is plenty to tell apps
+/// apart.
+const KEY_DISPLAY_LENGTH: usize = 12;
+
+/// Lists the apps in the user's extraction cache.
+pub fn list_cache() -> Result<()> {
+    let cache = AppCache::user()?;
+    let entries = cache.entries()?;
+    for entry in &entries {
+        println!(
+            "{} {:>12} {} {}",
+            short_key(&entry.key),
+            entry.size,
+            match entry.last_used.and_then(system_time_timestamp) {
+                Some(timestamp) => format_timestamp(timestamp),
+                None => format!("{:<19}", "never"),
+            },
+            describe_cache_entry(entry)
+        );
+    }
+    println!(
+        "{} apps, {} bytes in {}",
+        entries.len(),
+        entries.iter().map(|entry| entry.size).sum::<u64>(),
+        cache.root().display()
+    );
+    Ok(())
+}
+
+/// Removes cached apps that haven't been used for `older_than_days`, along
+/// with any whose extraction was interrupted.
+pub fn prune_cache(older_than_days: u64) -> Result<()> {
+    let cache = AppCache::user()?;
+    let cutoff = SystemTime::now() - Duration::from_secs(older_than_days * 24 * 60 * 60);
+    let entries = cache.entries()?.into_iter().filter(|entry| {
+        entry.record.is_none() || matches!(entry.last_used, Some(time) if time < cutoff)
+    });
+    remove_cache_entries(&cache, entries)
+}
+
+/// Re-hashes the files of every cached app, removing any that were changed
+/// since they were extracted so they're extracted afresh next time.
+pub fn verify_cache() -> Result<()> {
+    let cache = AppCache::user()?;
+    let mut changed = Vec::new();
+    for entry in cache.entries()? {
+        if cache.verify(&entry.key)? {
+            println!(
+                "Verified {} {}",
+                short_key(&entry.key),
+                describe_cache_entry(&entry)
+            );
+        } else {
+            changed.push(entry);
+        }
+    }
+    remove_cache_entries(&cache, changed.into_iter())
+}
+
+/// Removes every cached app that isn't running.
+pub fn clear_cache() -> Result<()> {
+    let cache = AppCache::user()?;
+    let entries = cache.entries()?;
+    remove_cache_entries(&cache, entries.into_iter())
+}
+
+fn remove_cache_entries<I: Iterator<Item = CacheEntry>>(
+    cache: &AppCache,
+    entries: I,
+) -> Result<()> {
+    let (mut removed, mut freed) = (0, 0);
+    for entry in entries {
+        let key = short_key(&entry.key);
+        if cache.remove(&entry.key)? {
+            println!("Removed {} {}", key, describe_cache_entry(&entry));
+            removed += 1;
+            freed += entry.size;
+        } else {
+            println!("Skipped {} {}", key, describe_cache_entry(&entry));
+        }
+    }
+    println!("Removed {} apps, freeing {} bytes.", removed, freed);
+    Ok(())
+}
+
+fn short_key(key: &str) -> String {
+    key.chars().take(KEY_DISPLAY_LENGTH).collect()
+}
+
+fn describe_cache_entry(entry: &CacheEntry) -> String {
+    let description = match &entry.record {
+        Some(CacheRecord {
+            name: Some(name),
+            version: Some(version),
+            ..
+        }) => format!("{} {}", name, version),
+        Some(CacheRecord {
+            name: Some(name), ..
+        }) => name.clone(),
+        Some(record) => record.entry.clone(),
+        None => "(incomplete)".to_owned(),
+    };
+    if entry.in_use {
+        format!("{} (running)", description)
+    } else {
+        description
+    }
+}
+
+fn system_time_timestamp(time: SystemTime) -> Option<i64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs() as i64)
+}
+
+/// Removes the directories apps were run from by loaders that were killed
+/// before they could remove them.
+pub fn collect_garbage(dry_run: bool) -> Result<()> {
+    let parent = instances_dir()?;
+    let (mut removed, mut failed) = (0, 0);
+    for instance in orphaned_instances(&parent)? {
+        let description = match instance.pid {
+            Some(pid) => format!("{} (process {})", instance.dir.display(), pid),
+            None => instance.dir.display().to_string(),
+        };
+        if dry_run {
+            println!("Would remove {}", description);
+            removed += 1;
+            continue;
+        }
+        match remove_orphaned_instance(&instance) {
+            Ok(true) => {
+                println!("Removed {}", description);
+                removed += 1;
+            }
+            Ok(false) => {}
+            Err(err) => {
+                eprintln!("Failed to remove {}: {}", description, err);
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "{} {} orphaned app directories in {}.",
+        if dry_run { "Found" } else { "Removed" },
+        removed,
+        parent.display()
+    );
+    if failed > 0 {
+        return Err(format!("{} orphaned app directories could not be removed.", failed).into());
+    }
     Ok(())
 }
 
@@ -69,9 +592,98 @@ pub fn check_app(app_path: PathBuf) -> Result<bool> {
     Ok(OnexFile::validate(&mut file).is_ok())
 }
 
-pub fn mount_app(app_path: PathBuf, mount_path: PathBuf) -> Result<()> {
+pub fn verify_app(
+    app_path: PathBuf,
+    public_key_path: Option<PathBuf>,
+    signature_path: Option<PathBuf>,
+) -> Result<bool> {
+    let mut onex_file = OnexFile::new(File::open(&app_path)?)?;
+    let result = onex_file.verify().and_then(|()| match public_key_path {
+        Some(public_key_path) => {
+            let public_key = read_hex_file(&public_key_path)?;
+            let signature = signature_path
+                .map(|path| read_hex_file(&path))
+                .transpose()?;
+            onex_file.verify_signature(&public_key, signature.as_deref())
+        }
+        None => Ok(()),
+    });
+
+    match result {
+        Ok(()) => Ok(true),
+        Err(err) => {
+            eprintln!("{}", err);
+            Ok(false)
+        }
+    }
+}
+
+pub fn generate_signing_key(secret_key_path: PathBuf, public_key_path: PathBuf) -> Result<()> {
+    // Keep the secret key to this user.
+    let mut secret_key_options = OpenOptions::new();
+    #[cfg(unix)]
+    secret_key_options.mode(0o600);
+    let mut secret_key_file = create_key_file(&secret_key_path, &mut secret_key_options)?;
+    let mut public_key_file = match create_key_file(&public_key_path, &mut OpenOptions::new()) {
+        Ok(file) => file,
+        Err(err) => {
+            drop(secret_key_file);
+            let _ = fs::remove_file(&secret_key_path);
+            return Err(err);
+        }
+    };
+
+    let (secret_key, public_key) = generate_keypair();
+    secret_key_file.write_all(to_hex(&secret_key).as_bytes())?;
+    public_key_file.write_all(to_hex(&public_key).as_bytes())?;
+    Ok(())
+}
+
+/// Creates a file for a new key, refusing to overwrite an existing one.
+fn create_key_file(path: &Path, options: &mut OpenOptions) -> Result<File> {
+    match options.write(true).create_new(true).open(path) {
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            Err(format!("{} already exists.", path.display()).into())
+        }
+        result => Ok(result?),
+    }
+}
+
+pub fn sign_app(
+    app_path: PathBuf,
+    secret_key_path: PathBuf,
+    output_path: Option<PathBuf>,
+    detached_path: Option<PathBuf>,
+) -> Result<()> {
+    let secret_key = read_hex_file(&secret_key_path)?;
     let mut onex_file = OnexFile::new(File::open(&app_path)?)?;
-    let seeker: Box<dyn ReadSeek> = Box::new(onex_file.data_accessor()?);
+    let (signature, patch) = onex_file.sign(&secret_key)?;
+    drop(onex_file);
+
+    if let Some(detached_path) = detached_path {
+        fs::write(&detached_path, to_hex(&signature.signature))?;
+        return Ok(());
+    }
+
+    let output_path = match output_path {
+        Some(output_path) => {
+            fs::copy(&app_path, &output_path)?;
+            output_path
+        }
+        None => app_path,
+    };
+    let mut output_file = OpenOptions::new().write(true).open(&output_path)?;
+    output_file.set_len(patch.offset)?;
+    output_file.seek(SeekFrom::End(0))?;
+    output_file.write_all(&patch.bytes)?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn mount_app(app_path: PathBuf, mount_path: PathBuf) -> Result<()> {
+    let onex_file = OnexFile::new(File::open(&app_path)?)?;
+    let seeker: Box<dyn ReadSeek> = Box::new(onex_file.into_data_accessor()?);
 
     let archive = ZipArchive::new(seeker)?;
     let _provider = ProjfsProvider::new(&mount_path, archive)?;
@@ -84,22 +696,204 @@ pub fn mount_app(app_path: PathBuf, mount_path: PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn get_loader_bytes(loader_path: Option<PathBuf>, architecture: String) -> Result<PathBuf> {
+fn kind_name(kind: &ArchiveEntryKind) -> &'static str {
+    match kind {
+        ArchiveEntryKind::File => "file",
+        ArchiveEntryKind::Directory => "directory",
+        ArchiveEntryKind::Symlink(_) => "symlink",
+    }
+}
+
+fn symlink_target(entry: &ArchiveEntry) -> Option<&str> {
+    match &entry.kind {
+        ArchiveEntryKind::Symlink(target) => Some(target),
+        _ => None,
+    }
+}
+
+/// Describes an entry's kind and permissions the way `ls -l` does, with
+/// dashes for permissions the archive didn't record.
+fn mode_string(entry: &ArchiveEntry) -> String {
+    let mut s = match entry.kind {
+        ArchiveEntryKind::File => "-",
+        ArchiveEntryKind::Directory => "d",
+        ArchiveEntryKind::Symlink(_) => "l",
+    }
+    .to_owned();
+    let mode = entry.mode.unwrap_or(0);
+    for shift in &[6, 3, 0] {
+        for (bit, c) in &[(4, 'r'), (2, 'w'), (1, 'x')] {
+            s.push(if (mode >> shift) & bit != 0 { *c } else { '-' });
+        }
+    }
+    s
+}
+
+/// Quotes a CSV field if it needs it.
+fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+#[derive(Default)]
+struct TreeNode<'a> {
+    children: BTreeMap<&'a str, TreeNode<'a>>,
+    entry: Option<&'a ArchiveEntry>,
+}
+
+/// Draws the entries as a tree, including any directories they're in that
+/// the archive has no entries for.
+fn print_tree(entries: &[ArchiveEntry]) {
+    let mut root = TreeNode::default();
+    for entry in entries {
+        let node = entry
+            .path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .fold(&mut root, |node, component| {
+                node.children.entry(component).or_default()
+            });
+        node.entry = Some(entry);
+    }
+
+    println!(".");
+    print_tree_children(&root, "");
+}
+
+fn print_tree_children(node: &TreeNode, prefix: &str) {
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i == node.children.len() - 1;
+        let suffix = match child.entry.map(|entry| &entry.kind) {
+            Some(ArchiveEntryKind::Symlink(target)) => format!(" -> {}", target),
+            _ if !child.children.is_empty() || matches!(child.entry, Some(e) if e.is_dir()) => {
+                "/".to_owned()
+            }
+            _ => String::new(),
+        };
+        println!(
+            "{}{}{}{}",
+            prefix,
+            if last { "└── " } else { "├── " },
+            name,
+            suffix
+        );
+        print_tree_children(
+            child,
+            &format!("{}{}", prefix, if last { "    " } else { "│   " }),
+        );
+    }
+}
+
+/// The platform a loader runs on, as read from its headers.
+struct LoaderTarget {
+    /// The architecture, named as `--arch` names it.
+    arch: String,
+    executable: bool,
+    /// Whether the loader carries an Authenticode signature.
+    signed: bool,
+}
+
+impl LoaderTarget {
+    fn read<R: Read + Seek>(r: &mut R) -> Option<Self> {
+        if let Ok(headers) = PeHeaders::read(r) {
+            Some(LoaderTarget {
+                arch: headers.machine.to_string(),
+                executable: headers.is_executable(),
+                signed: headers.certificate_table.is_some(),
+            })
+        } else if let Ok(header) = ElfHeader::read(r) {
+            Some(LoaderTarget {
+                arch: match header.arch() {
+                    Some(arch) => format!("{}-linux", arch),
+                    None => format!("unknown ELF machine {:#06x}", header.machine),
+                },
+                executable: header.is_executable(),
+                signed: false,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+fn read_hex_file(path: &Path) -> Result<Vec<u8>> {
+    from_hex(&fs::read_to_string(path)?)
+}
+
+/// Finds the loader to use, the one bundled for `architecture` (or the host
+/// architecture) if no path is given, and checks that it's an executable for
+/// the right architecture. If `verbose`, says which loader it is.
+fn get_loader_bytes(
+    loader_path: Option<PathBuf>,
+    architecture: Option<String>,
+    verbose: bool,
+) -> Result<PathBuf> {
     let exe_path = env::current_exe()?;
-    match loader_path {
-        Some(path) => Ok(path),
+    let (loader_path, architecture) = match loader_path {
+        Some(path) => (path, architecture),
         None => {
+            let architecture = architecture.unwrap_or_else(|| env::consts::ARCH.to_owned());
             let loader_name = match architecture.as_str() {
                 "x86_64" => "onex_loader_x64.exe",
                 "aarch64" => "onex_loader_arm64.exe",
+                "x86_64-linux" => "onex_loader_x64_linux",
                 a => return Err(format!("'{}' is not a supported loader architecture", a).into()),
             };
             match exe_path.parent() {
-                Some(path) => Ok(path.join(loader_name)),
+                Some(path) => (path.join(loader_name), Some(architecture)),
                 // This should never be reached, since the parent of a file should
                 // always be its containing directory.
                 None => unreachable!(),
             }
         }
+    };
+
+    let target = match LoaderTarget::read(&mut File::open(&loader_path)?) {
+        Some(target) => target,
+        None => {
+            return Err(format!(
+                "The loader {} is not a Windows or Linux executable.",
+                loader_path.display()
+            )
+            .into())
+        }
+    };
+    if !target.executable {
+        return Err(format!(
+            "The loader {} is a library or object file, not an executable.",
+            loader_path.display()
+        )
+        .into());
+    }
+    if let Some(architecture) = architecture {
+        if target.arch != architecture {
+            return Err(format!(
+                "The loader {} is built for {}, not {}.",
+                loader_path.display(),
+                target.arch,
+                architecture
+            )
+            .into());
+        }
+    }
+
+    if target.signed {
+        eprintln!(
+            "Warning: the loader {} is Authenticode signed, and appending the app to it will invalidate that signature. Sign the packed app instead.",
+            loader_path.display()
+        );
+    }
+
+    // This goes to stderr so it doesn't get mixed up with any output.
+    if verbose {
+        eprintln!(
+            "Using the {} loader {}.",
+            target.arch,
+            loader_path.display()
+        );
     }
+    Ok(loader_path)
 }
@@ -1,10 +1,18 @@
 use {
-    std::{env, path::PathBuf, process},
+    onex::{InfoFormat, ListFormat},
+    std::{path::PathBuf, process},
     structopt::StructOpt,
-    util::Result,
+    util::{
+        parse_compression_method, ArchiveOptions, Compression, ExistingFilePolicy, ExtractOptions,
+        Result, UnsafePathPolicy, WorkingDir,
+    },
 };
 
-const SUPPORTED_ARCHES: [&str; 2] = ["x86_64", "aarch64"];
+const SUPPORTED_ARCHES: [&str; 3] = ["x86_64", "aarch64", "x86_64-linux"];
+const COMPRESSION_METHODS: [&str; 4] = ["stored", "deflate", "zstd", "bzip2"];
+const LIST_FORMATS: [&str; 3] = ["text", "json", "csv"];
+const INFO_FORMATS: [&str; 2] = ["text", "json"];
+const WORKING_DIRS: [&str; 2] = ["caller", "app-root"];
 
 #[derive(StructOpt)]
 struct Opt {
@@ -12,6 +20,8 @@ struct Opt {
     subcommand: Subcommand,
 }
 
+// Only one of these is ever made, so Pack being big doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(StructOpt)]
 enum Subcommand {
     /// create a single-file executable
@@ -25,12 +35,80 @@ enum Subcommand {
         output_path: PathBuf,
 
         /// onex_loader.exe file (default use loader bundled with onex.exe)
-        #[structopt(long = "loader", conflicts_with("architecture"), parse(from_os_str))]
+        #[structopt(long = "loader", parse(from_os_str))]
         loader_path: Option<PathBuf>,
 
-        /// The architecture of the loader you want to use (default the host architecture)
-        #[structopt(long = "arch", possible_values(&SUPPORTED_ARCHES), default_value = env::consts::ARCH)]
-        architecture: String,
+        /// The architecture of the loader you want to use, which a given --loader must match (default the host architecture's Windows loader, even on Linux)
+        #[structopt(long = "arch", possible_values(&SUPPORTED_ARCHES))]
+        architecture: Option<String>,
+
+        /// a TOML manifest describing how to run the app (default use the app's onex_run file)
+        #[structopt(long = "manifest", parse(from_os_str))]
+        manifest_path: Option<PathBuf>,
+
+        /// the executable to run, relative to the app directory (overrides the manifest)
+        #[structopt(long = "entry")]
+        entry: Option<String>,
+
+        /// an argument always passed to the entry, may be given more than once
+        #[structopt(long = "arg", number_of_values(1), allow_hyphen_values(true))]
+        args: Vec<String>,
+
+        /// an environment variable to set for the entry, as <name>=<value>, may be given more than once
+        #[structopt(long = "env", number_of_values(1))]
+        env: Vec<String>,
+
+        /// where to start the entry: where the packed app was started from, or the root of the unpacked app
+        #[structopt(long = "working-dir", possible_values(&WORKING_DIRS))]
+        working_dir: Option<String>,
+
+        /// also record a digest of the loader, so `verify` checks it too, leaving out the fields Authenticode signing fills in
+        #[structopt(long = "digest-loader")]
+        digest_loader: bool,
+
+        /// run the app from a per-user cache, instead of unpacking it afresh on every launch
+        #[structopt(long = "cache")]
+        cache: bool,
+
+        /// how to compress the app's files
+        #[structopt(long = "compression", possible_values(&COMPRESSION_METHODS), default_value = "stored")]
+        compression: String,
+
+        /// the compression level to use (default the method's own default)
+        #[structopt(long = "level", allow_hyphen_values(true))]
+        level: Option<i32>,
+
+        /// compress files with an extension differently, as <extension>=<method>, may be given more than once
+        #[structopt(long = "compression-for", number_of_values(1))]
+        compression_overrides: Vec<String>,
+
+        /// produce the same bytes every time the same files are packed, timestamped in UTC with SOURCE_DATE_EPOCH if it's set
+        #[structopt(long = "reproducible")]
+        reproducible: bool,
+
+        /// only pack files matching this gitignore-style pattern, may be given more than once
+        #[structopt(long = "include", number_of_values(1))]
+        include: Vec<String>,
+
+        /// don't pack files matching this gitignore-style pattern, on top of those in the app's .onexignore, may be given more than once
+        #[structopt(long = "exclude", number_of_values(1))]
+        exclude: Vec<String>,
+
+        /// pack the files symlinks point to, instead of the symlinks themselves
+        #[structopt(long = "dereference")]
+        dereference_symlinks: bool,
+
+        /// also record modification times in extended timestamp fields, which are in UTC rather than local time and keep odd seconds and times outside 1980-2107
+        #[structopt(long = "extended-timestamps")]
+        extended_timestamps: bool,
+
+        /// list the files that would be packed, without packing them
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+
+        /// say which loader is used
+        #[structopt(short = "v", long = "verbose")]
+        verbose: bool,
     },
     /// swap out a loader in one packed app for another
     Swap {
@@ -46,9 +124,17 @@ enum Subcommand {
         #[structopt(long = "output", conflicts_with("architecture"), parse(from_os_str))]
         output_path: Option<PathBuf>,
 
-        /// The architecture of the loader you want to use (default the host architecture)
-        #[structopt(long = "arch", possible_values(&SUPPORTED_ARCHES), default_value = env::consts::ARCH)]
-        architecture: String,
+        /// The architecture of the loader you want to use, which a given --loader must match (default the host architecture's Windows loader, even on Linux)
+        #[structopt(long = "arch", possible_values(&SUPPORTED_ARCHES))]
+        architecture: Option<String>,
+
+        /// also record a digest of the new loader, so `verify` checks it too
+        #[structopt(long = "digest-loader")]
+        digest_loader: bool,
+
+        /// say which loader is used
+        #[structopt(short = "v", long = "verbose")]
+        verbose: bool,
     },
 
     /// List the contents of an onex app
@@ -56,6 +142,18 @@ enum Subcommand {
         /// the packaged app you want to list the contents of
         #[structopt(parse(from_os_str))]
         app_path: PathBuf,
+
+        /// how to print the entries, where json and csv include everything --long shows
+        #[structopt(long = "format", possible_values(&LIST_FORMATS), default_value = "text")]
+        format: String,
+
+        /// show each entry's mode, size, compressed size, compression method, CRC-32 and modification time
+        #[structopt(short = "l", long = "long", conflicts_with("tree"))]
+        long: bool,
+
+        /// show the entries as a tree
+        #[structopt(long = "tree")]
+        tree: bool,
     },
 
     /// Extract the contents of an onex app
@@ -67,6 +165,50 @@ enum Subcommand {
         /// the directory to extract to
         #[structopt(parse(from_os_str))]
         output_path: PathBuf,
+
+        /// only extract entries matching these gitignore-style patterns, or inside directories that do
+        patterns: Vec<String>,
+
+        /// extract entries whose names lead outside the output directory by dropping the offending parts, instead of failing
+        #[structopt(long = "sanitize-paths")]
+        sanitize_paths: bool,
+
+        /// drop this many leading directories from each entry's path, skipping entries with nothing left
+        #[structopt(long = "strip-components", default_value = "0")]
+        strip_components: usize,
+
+        /// leave files that already exist alone
+        #[structopt(long = "skip-existing", conflicts_with_all(&["overwrite", "fail-if-exists"]))]
+        skip_existing: bool,
+
+        /// replace files that already exist (the default)
+        #[structopt(long = "overwrite", conflicts_with("fail-if-exists"))]
+        overwrite: bool,
+
+        /// fail without extracting anything if a file already exists
+        #[structopt(long = "fail-if-exists")]
+        fail_if_exists: bool,
+    },
+
+    /// Write one file from an onex app to stdout
+    Cat {
+        /// the packaged app to read from
+        #[structopt(parse(from_os_str))]
+        app_path: PathBuf,
+
+        /// the path of the file within the app
+        path: String,
+    },
+
+    /// Show how an onex app is put together and run
+    Info {
+        /// the packaged app you want to know about
+        #[structopt(parse(from_os_str))]
+        app_path: PathBuf,
+
+        /// how to print what's found
+        #[structopt(long = "format", possible_values(&INFO_FORMATS), default_value = "text")]
+        format: String,
     },
 
     /// Succeeds if the given file is an onex app, fails otherwise
@@ -76,7 +218,66 @@ enum Subcommand {
         app_path: PathBuf,
     },
 
+    /// Succeeds if the app's contents match the digests recorded when it was packed
+    Verify {
+        /// the packaged app you want to verify
+        #[structopt(parse(from_os_str))]
+        app_path: PathBuf,
+
+        /// also require the payload to be signed by this public key (as written by `keygen`)
+        #[structopt(long = "pubkey", parse(from_os_str))]
+        public_key_path: Option<PathBuf>,
+
+        /// check this detached signature instead of the one embedded in the app
+        #[structopt(long = "signature", requires("public-key-path"), parse(from_os_str))]
+        signature_path: Option<PathBuf>,
+    },
+
+    /// Generate an Ed25519 key pair for signing apps
+    Keygen {
+        /// where to write the secret key
+        #[structopt(parse(from_os_str))]
+        secret_key_path: PathBuf,
+
+        /// where to write the public key
+        #[structopt(parse(from_os_str))]
+        public_key_path: PathBuf,
+    },
+
+    /// Sign the payload of an onex app
+    Sign {
+        /// the packaged app you want to sign
+        #[structopt(parse(from_os_str))]
+        app_path: PathBuf,
+
+        /// the secret key to sign with (as written by `keygen`)
+        #[structopt(long = "key", parse(from_os_str))]
+        secret_key_path: PathBuf,
+
+        /// the signed exe to be generated (default modify in place)
+        #[structopt(long = "output", conflicts_with("detached-path"), parse(from_os_str))]
+        output_path: Option<PathBuf>,
+
+        /// write the signature to this file instead of embedding it in the app
+        #[structopt(long = "detached", parse(from_os_str))]
+        detached_path: Option<PathBuf>,
+    },
+
+    /// Manage the per-user cache apps packed with --cache run from
+    Cache {
+        #[structopt(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Remove the directories left behind by packed apps whose loader was killed
+    Gc {
+        /// list the directories that would be removed, without removing them
+        #[structopt(long = "dry-run")]
+        dry_run: bool,
+    },
+
     /// Mounts the onex app to the given directory
+    #[cfg(windows)]
     Mount {
         /// the packaged app you want to mount
         #[structopt(parse(from_os_str))]
@@ -88,6 +289,22 @@ enum Subcommand {
     },
 }
 
+#[derive(StructOpt)]
+enum CacheCommand {
+    /// list the cached apps, most recently used first
+    List,
+    /// remove cached apps that haven't been used in a while, and any left incomplete
+    Prune {
+        /// remove apps that haven't been used in this many days
+        #[structopt(long = "older-than", default_value = "30")]
+        older_than_days: u64,
+    },
+    /// remove every cached app that isn't running
+    Clear,
+    /// re-hash every cached app's files, removing apps that were changed since they were extracted
+    Verify,
+}
+
 fn main() -> Result<()> {
     let opt = Opt::from_args();
     let exit_code = match opt.subcommand {
@@ -96,18 +313,136 @@ fn main() -> Result<()> {
             output_path,
             loader_path,
             architecture,
-        } => onex::package_app(app_dir, output_path, loader_path, architecture).map(|_| 0),
+            manifest_path,
+            entry,
+            args,
+            env,
+            working_dir,
+            digest_loader,
+            cache,
+            compression,
+            level,
+            compression_overrides,
+            reproducible,
+            include,
+            exclude,
+            dereference_symlinks,
+            extended_timestamps,
+            dry_run,
+            verbose,
+        } => {
+            let mut compression = Compression::new(parse_compression_method(&compression)?, level)?;
+            for compression_override in compression_overrides {
+                compression.add_override(&compression_override)?;
+            }
+            let reproducible_timestamp = if reproducible {
+                Some(ArchiveOptions::reproducible_timestamp()?)
+            } else {
+                None
+            };
+            onex::package_app(
+                app_dir,
+                output_path,
+                loader_path,
+                architecture,
+                onex::PackOptions {
+                    manifest_path,
+                    entry,
+                    args,
+                    env,
+                    working_dir: working_dir.map(|working_dir| match working_dir.as_str() {
+                        "app-root" => WorkingDir::AppRoot,
+                        _ => WorkingDir::Caller,
+                    }),
+                    digest_loader,
+                    cache,
+                    archive: ArchiveOptions {
+                        compression,
+                        reproducible_timestamp,
+                        include,
+                        exclude,
+                        dereference_symlinks,
+                        extended_timestamps,
+                        ..Default::default()
+                    },
+                    dry_run,
+                    verbose,
+                },
+            )
+        }
+        .map(|_| 0),
         Subcommand::Swap {
             app_path,
             loader_path,
             output_path,
             architecture,
-        } => onex::swap_app_loader(app_path, loader_path, output_path, architecture).map(|_| 0),
-        Subcommand::List { app_path } => onex::list_app_contents(app_path).map(|_| 0),
+            digest_loader,
+            verbose,
+        } => onex::swap_app_loader(
+            app_path,
+            loader_path,
+            output_path,
+            architecture,
+            digest_loader,
+            verbose,
+        )
+        .map(|_| 0),
+        Subcommand::List {
+            app_path,
+            format,
+            long,
+            tree,
+        } => {
+            let format = match (format.as_str(), long, tree) {
+                ("text", true, _) => ListFormat::Long,
+                ("text", _, true) => ListFormat::Tree,
+                ("text", false, false) => ListFormat::Text,
+                ("json", false, false) => ListFormat::Json,
+                ("csv", false, false) => ListFormat::Csv,
+                _ => return Err("--long and --tree only apply to the text format.".into()),
+            };
+            onex::list_app_contents(app_path, format).map(|_| 0)
+        }
         Subcommand::Extract {
             app_path,
             output_path,
-        } => onex::extract_app_contents(app_path, output_path).map(|_| 0),
+            patterns,
+            sanitize_paths,
+            strip_components,
+            skip_existing,
+            overwrite,
+            fail_if_exists,
+        } => {
+            let unsafe_paths = if sanitize_paths {
+                UnsafePathPolicy::Sanitize
+            } else {
+                UnsafePathPolicy::Reject
+            };
+            let existing_files = match (skip_existing, overwrite, fail_if_exists) {
+                (true, _, _) => ExistingFilePolicy::Skip,
+                (_, false, true) => ExistingFilePolicy::Fail,
+                _ => ExistingFilePolicy::Overwrite,
+            };
+            onex::extract_app_contents(
+                app_path,
+                output_path,
+                ExtractOptions {
+                    unsafe_paths,
+                    patterns,
+                    strip_components,
+                    existing_files,
+                },
+            )
+            .map(|_| 0)
+        }
+        Subcommand::Cat { app_path, path } => onex::cat_app_file(app_path, path).map(|_| 0),
+        Subcommand::Info { app_path, format } => {
+            let format = match format.as_str() {
+                "json" => InfoFormat::Json,
+                _ => InfoFormat::Text,
+            };
+            onex::app_info(app_path, format).map(|_| 0)
+        }
         Subcommand::Check { app_path } => {
             if onex::check_app(app_path)? {
                 Ok(0)
@@ -116,6 +451,36 @@ fn main() -> Result<()> {
                 Ok(1)
             }
         }
+        Subcommand::Verify {
+            app_path,
+            public_key_path,
+            signature_path,
+        } => {
+            if onex::verify_app(app_path, public_key_path, signature_path)? {
+                Ok(0)
+            } else {
+                Ok(1)
+            }
+        }
+        Subcommand::Keygen {
+            secret_key_path,
+            public_key_path,
+        } => onex::generate_signing_key(secret_key_path, public_key_path).map(|_| 0),
+        Subcommand::Sign {
+            app_path,
+            secret_key_path,
+            output_path,
+            detached_path,
+        } => onex::sign_app(app_path, secret_key_path, output_path, detached_path).map(|_| 0),
+        Subcommand::Cache { command } => match command {
+            CacheCommand::List => onex::list_cache(),
+            CacheCommand::Prune { older_than_days } => onex::prune_cache(older_than_days),
+            CacheCommand::Clear => onex::clear_cache(),
+            CacheCommand::Verify => onex::verify_cache(),
+        }
+        .map(|_| 0),
+        Subcommand::Gc { dry_run } => onex::collect_garbage(dry_run).map(|_| 0),
+        #[cfg(windows)]
         Subcommand::Mount {
             app_path,
             mount_path,
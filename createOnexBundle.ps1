@@ -29,14 +29,12 @@ try {
     }
 
     Copy-Item .\target\release\onex.exe .\target\onex_bundle
-    Write-Output 'onex.exe' > .\target\onex_bundle\onex_run
-    .\target\release\onex.exe pack .\target\onex_bundle .\target\onex_bundle_output\onex_x64.exe --loader .\target\onex_bundle\onex_loader_x64.exe
+    .\target\release\onex.exe pack .\target\onex_bundle .\target\onex_bundle_output\onex_x64.exe --entry onex.exe --loader .\target\onex_bundle\onex_loader_x64.exe
     if (-not $?) { Write-Error 'x64 packaging failed' }
 
     if ($BuildArm) {
         Copy-Item .\target\aarch64-pc-windows-msvc\release\onex.exe .\target\onex_bundle
-        Write-Output 'onex.exe' > .\target\onex_bundle\onex_run
-        .\target\release\onex.exe pack .\target\onex_bundle .\target\onex_bundle_output\onex_arm64.exe --loader .\target\onex_bundle\onex_loader_arm64.exe
+        .\target\release\onex.exe pack .\target\onex_bundle .\target\onex_bundle_output\onex_arm64.exe --entry onex.exe --loader .\target\onex_bundle\onex_loader_arm64.exe
         if (-not $?) { Write-Error 'arm64 packaging failed' }
     }
 } finally {
@@ -1,7 +1,14 @@
 use {
     log::error,
-    std::{io, mem, path::PathBuf, ptr},
-    util::{to_u16_vec, Result},
+    std::{
+        env,
+        ffi::OsString,
+        io, mem,
+        os::windows::ffi::OsStrExt,
+        path::{Path, PathBuf},
+        ptr,
+    },
+    util::{build_command_line, to_u16_vec, Result},
     winapi::{
         ctypes::c_void,
         shared::ntdef::{FALSE, TRUE},
@@ -97,7 +104,16 @@ impl Drop for WaitableJob {
 }
 
 /// Based on https://devblogs.microsoft.com/oldnewthing/20130405-00/?p=4743
-pub fn create_process_in_job_object(exe_file: PathBuf, args: Vec<String>) -> Result<WaitableJob> {
+pub fn create_process_in_job_object(
+    exe_file: PathBuf,
+    args: Vec<String>,
+    vars: &[(OsString, OsString)],
+    current_dir: Option<&Path>,
+) -> Result<WaitableJob> {
+    let command_line = build_command_line(&exe_file.to_string_lossy(), &args)?;
+    let mut environment = environment_block(vars);
+    let current_dir = current_dir.map(to_u16_vec);
+
     let job = handle_get_last_error! { unsafe {
         CreateJobObjectW(
             ptr::null_mut(), // lpJobAttributes
@@ -127,12 +143,6 @@ pub fn create_process_in_job_object(exe_file: PathBuf, args: Vec<String>) -> Res
         )
     }};
 
-    let command_line = format!(
-        "{} {}",
-        (*exe_file.to_string_lossy()).to_owned(),
-        &args.join(" ")
-    );
-
     let mut startup_info = unsafe { mem::zeroed::<STARTUPINFOW>() };
     startup_info.cb = mem::size_of::<STARTUPINFOW>() as u32;
 
@@ -146,8 +156,8 @@ pub fn create_process_in_job_object(exe_file: PathBuf, args: Vec<String>) -> Res
             ptr::null_mut(), // lpThreadAttributes
             TRUE.into(),     // bInheritHandles
             CREATE_SUSPENDED | CREATE_UNICODE_ENVIRONMENT,
-            ptr::null_mut(), // lpEnvironment
-            ptr::null(),     // lpCurrentDirectory
+            environment.as_mut_ptr().cast::<c_void>(),
+            current_dir.as_ref().map_or(ptr::null(), |dir| dir.as_ptr()),
             &mut startup_info,
             &mut process_info,
         )
@@ -165,3 +175,31 @@ pub fn create_process_in_job_object(exe_file: PathBuf, args: Vec<String>) -> Res
         process: process_info.hProcess,
     })
 }
+
+/// Builds a Unicode environment block holding this process's variables with
+/// `vars` set on top, sorted by name as Windows expects.
+fn environment_block(vars: &[(OsString, OsString)]) -> Vec<u16> {
+    // Windows variable names aren't case sensitive.
+    let name_key = |name: &OsString| name.to_string_lossy().to_uppercase();
+
+    let mut all_vars = env::vars_os().collect::<Vec<_>>();
+    for (name, value) in vars {
+        all_vars.retain(|(existing, _)| name_key(existing) != name_key(name));
+        all_vars.push((name.clone(), value.clone()));
+    }
+    all_vars.sort_by_key(|(name, _)| name_key(name));
+
+    let mut block = Vec::new();
+    for (name, value) in all_vars {
+        block.extend(name.encode_wide());
+        block.push(u16::from(b'='));
+        block.extend(value.encode_wide());
+        block.push(0);
+    }
+    // The block ends with an empty string, and can't be entirely empty.
+    if block.is_empty() {
+        block.push(0);
+    }
+    block.push(0);
+    block
+}
@@ -1 +1,4 @@
+#[cfg(windows)]
 pub mod job_object;
+#[cfg(unix)]
+pub mod runtime_dir;
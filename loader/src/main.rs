@@ -1,63 +1,187 @@
 use {
-    onex_loader::job_object::create_process_in_job_object,
-    std::{
-        env,
-        fs::{self, File},
-        path::PathBuf,
-        process,
+    log::error,
+    std::{env, ffi::OsString, fs::File, path::Path, process, thread},
+    util::{
+        from_hex, instances_dir, sweep_orphaned_instances, AppCache, Digest, Manifest,
+        OffsetSeeker, OnexFile, Result, WorkingDir, APP_DIR_VAR, EXE_PATH_VAR, VERSION_VAR,
     },
-    util::{get_temp_dir, OffsetSeeker, OnexFile, ProjfsProvider, ReadSeek, Result},
+};
+#[cfg(windows)]
+use {
+    onex_loader::job_object::create_process_in_job_object,
+    std::path::PathBuf,
+    util::{Instance, ProjfsProvider, ReadSeek, INSTANCE_PREFIX},
     uuid::Uuid,
     winapi::um::wincon::FreeConsole,
     zip::ZipArchive,
 };
+#[cfg(unix)]
+use {
+    onex_loader::runtime_dir::RuntimeDir,
+    std::{
+        fs,
+        os::unix::{fs::PermissionsExt, process::ExitStatusExt},
+        process::Command,
+    },
+    util::{extract_zip, UnsafePathPolicy},
+};
+
+/// When set at build time, the loader refuses to run any payload that isn't
+/// signed by this hex-encoded Ed25519 public key.
+const TRUSTED_PUBLIC_KEY: Option<&str> = option_env!("ONEX_TRUSTED_PUBLIC_KEY");
 
 fn main() -> Result<()> {
     enable_logging();
 
+    // Clean up after loaders that were killed before they could, without
+    // holding up this app.
+    thread::spawn(|| {
+        if let Err(err) = instances_dir().and_then(|dir| sweep_orphaned_instances(&dir)) {
+            error!("Failed to sweep orphaned instances: {}", err);
+        }
+    });
+
     let exe_path = env::current_exe()?;
     let mut file = OnexFile::new(File::open(exe_path)?)?;
+    if let Some(public_key) = TRUSTED_PUBLIC_KEY {
+        file.verify_signature(&from_hex(public_key)?, None)?;
+    }
 
-    let seeker = file.data_accessor()?;
-    let exit_code = run_app(seeker)?;
-    process::exit(exit_code as i32);
+    let manifest = file.manifest()?;
+    let payload_digest = file.payload_digest();
+    let seeker = file.into_data_accessor()?;
+    let exit_code = run_app(seeker, payload_digest, manifest)?;
+    process::exit(exit_code);
 }
 
-fn run_app(seeker: OffsetSeeker) -> Result<u32> {
-    let mut uuid_buffer = Uuid::encode_buffer();
-    let instance_id = Uuid::new_v4()
-        .to_hyphenated()
-        .encode_lower(&mut uuid_buffer);
-    let dir_name = format!("onex_{}", instance_id);
-    let temp_dir = [get_temp_dir()?, PathBuf::from(dir_name)]
-        .iter()
-        .collect::<PathBuf>();
+#[cfg(windows)]
+fn run_app(
+    seeker: OffsetSeeker<File>,
+    payload_digest: Option<Digest>,
+    manifest: Manifest,
+) -> Result<i32> {
+    let _cached;
+    let _instance;
+    let _provider;
+    let app_dir = if manifest.cache {
+        _cached = AppCache::user()?.open(seeker, payload_digest, &manifest)?;
+        _cached.path().to_owned()
+    } else {
+        let mut uuid_buffer = Uuid::encode_buffer();
+        let instance_id = Uuid::new_v4()
+            .to_hyphenated()
+            .encode_lower(&mut uuid_buffer);
+        let dir_name = format!("{}{}", INSTANCE_PREFIX, instance_id);
+        let instance = Instance::claim(&instances_dir()?, &dir_name)?;
+        let temp_dir = instance.dir().to_owned();
+        _instance = instance;
 
-    let seeker: Box<dyn ReadSeek> = Box::new(seeker);
-    let archive = ZipArchive::new(seeker)?;
-    let _provider = ProjfsProvider::new(&temp_dir, archive)?;
+        let seeker: Box<dyn ReadSeek> = Box::new(seeker);
+        let archive = ZipArchive::new(seeker)?;
+        _provider = ProjfsProvider::new(&temp_dir, archive)?;
+        temp_dir
+    };
 
-    let exe_name_file = [&temp_dir, &PathBuf::from("onex_run")]
-        .iter()
-        .collect::<PathBuf>();
-    let exe_name = fs::read_to_string(exe_name_file)?.trim().to_owned();
-    let exe_file = [&temp_dir, &PathBuf::from(exe_name)]
+    let exe_file = [&app_dir, &PathBuf::from(&manifest.entry)]
         .iter()
         .collect::<PathBuf>();
 
-    let args = env::args().skip(1).collect::<Vec<String>>();
-    let job = create_process_in_job_object(exe_file, args)?;
+    let vars = app_environment(&manifest, &app_dir)?;
+    let current_dir = app_current_dir(&manifest, &app_dir);
+    let args = manifest
+        .args
+        .into_iter()
+        .chain(env::args().skip(1))
+        .collect::<Vec<String>>();
+    let job = create_process_in_job_object(exe_file, args, &vars, current_dir)?;
 
     unsafe { FreeConsole() };
     let exit_code = job.wait()?;
-    Ok(exit_code)
+    Ok(exit_code as i32)
+}
+
+/// Unpacks the app to a private directory, since there's no ProjFS to
+/// project it from, and runs it there.
+#[cfg(unix)]
+fn run_app(
+    seeker: OffsetSeeker<File>,
+    payload_digest: Option<Digest>,
+    manifest: Manifest,
+) -> Result<i32> {
+    let cached;
+    let runtime_dir;
+    let app_dir = if manifest.cache {
+        cached = AppCache::user()?.open(seeker, payload_digest, &manifest)?;
+        cached.path()
+    } else {
+        runtime_dir = RuntimeDir::create()?;
+        extract_zip(seeker, runtime_dir.path(), UnsafePathPolicy::Reject)?;
+        runtime_dir.path()
+    };
+
+    // Apps packed on Windows don't record any permissions, so the entry may
+    // not have come out executable.
+    let exe_file = app_dir.join(&manifest.entry);
+    let mut permissions = fs::metadata(&exe_file)?.permissions();
+    if permissions.mode() & 0o100 == 0 {
+        permissions.set_mode(permissions.mode() | 0o100);
+        fs::set_permissions(&exe_file, permissions)?;
+    }
+
+    let mut command = Command::new(&exe_file);
+    command.envs(app_environment(&manifest, app_dir)?);
+    if let Some(current_dir) = app_current_dir(&manifest, app_dir) {
+        command.current_dir(current_dir);
+    }
+    let args = manifest
+        .args
+        .into_iter()
+        .chain(env::args().skip(1))
+        .collect::<Vec<String>>();
+    let mut child = command.args(args).spawn()?;
+
+    // Like a shell, leave Ctrl-C and friends to the app, and stay around to
+    // clean up once it's handled them.
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+    }
+    let status = child.wait()?;
+
+    Ok(match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    })
+}
+
+/// The variables the entry is started with on top of the loader's own: the
+/// manifest's, then those telling the app where it's running from.
+fn app_environment(manifest: &Manifest, app_dir: &Path) -> Result<Vec<(OsString, OsString)>> {
+    let mut vars = manifest
+        .env
+        .iter()
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect::<Vec<(OsString, OsString)>>();
+    vars.push((APP_DIR_VAR.into(), app_dir.into()));
+    vars.push((EXE_PATH_VAR.into(), env::current_exe()?.into()));
+    vars.push((VERSION_VAR.into(), env!("CARGO_PKG_VERSION").into()));
+    Ok(vars)
+}
+
+/// Where to start the entry, or `None` to start it where the loader was.
+fn app_current_dir<'a>(manifest: &Manifest, app_dir: &'a Path) -> Option<&'a Path> {
+    match manifest.working_dir {
+        WorkingDir::Caller => None,
+        WorkingDir::AppRoot => Some(app_dir),
+    }
 }
 
 #[cfg(debug_assertions)]
 fn enable_logging() {
     flexi_logger::Logger::with_str("trace")
         .log_to_file()
-        .directory(get_temp_dir().unwrap())
+        .directory(instances_dir().unwrap())
         .discriminant("onex")
         .print_message()
         .start()
@@ -0,0 +1,50 @@
+use {
+    log::{error, trace},
+    std::{fs::DirBuilder, os::unix::fs::DirBuilderExt, path::Path},
+    util::{force_remove_dir_all, instances_dir, Instance, Result, INSTANCE_PREFIX},
+    uuid::Uuid,
+};
+
+/// A directory only the current user can get into, which an app is unpacked to
+/// while it runs and which is deleted when dropped.
+pub struct RuntimeDir {
+    instance: Instance,
+}
+
+impl RuntimeDir {
+    /// Creates a new directory under `$XDG_RUNTIME_DIR`, or the temporary
+    /// directory if that isn't set.
+    pub fn create() -> Result<Self> {
+        let mut uuid_buffer = Uuid::encode_buffer();
+        let instance_id = Uuid::new_v4()
+            .to_hyphenated()
+            .encode_lower(&mut uuid_buffer);
+        let instance = Instance::claim(
+            &instances_dir()?,
+            &format!("{}{}", INSTANCE_PREFIX, instance_id),
+        )?;
+
+        trace!("Creating runtime directory {}", instance.dir().display());
+        DirBuilder::new().mode(0o700).create(instance.dir())?;
+        Ok(RuntimeDir { instance })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.instance.dir()
+    }
+}
+
+impl Drop for RuntimeDir {
+    fn drop(&mut self) {
+        // The instance, and with it the lock file, goes once this is done.
+        let path = self.instance.dir();
+        trace!("Removing runtime directory {}", path.display());
+        if let Err(err) = force_remove_dir_all(path) {
+            error!(
+                "Failed to remove runtime directory {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+}
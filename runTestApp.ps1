@@ -8,6 +8,9 @@ try {
     if (Test-Path .\target\extracted) {
         Remove-Item -Recurse .\target\extracted
     }
+    if (Test-Path .\target\extracted_exe) {
+        Remove-Item -Recurse .\target\extracted_exe
+    }
 
     cargo build
     if (-not $?) { Write-Error 'Command failed' }
@@ -33,6 +36,33 @@ try {
     .\target\testapp_packaged.exe arg1 arg2 arg3
     if (-not $?) { Write-Error 'Command failed' }
 
+    .\target\onex_bundle_output\onex_x64.exe pack .\target\testapp .\target\testapp_packaged.exe --entry testapp.exe --arg arg1
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\testapp_packaged.exe arg2 arg3
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe pack .\target\testapp .\target\testapp_packaged.exe --entry testapp.exe --arg 'with space' --env ONEX_TEST=1 --working-dir app-root
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\testapp_packaged.exe 'another space' 'trailing\' '\"quoted\"'
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe pack .\target\testapp .\target\testapp_packaged.exe --compression zstd --level 19 --compression-for exe=deflate
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\testapp_packaged.exe arg1 arg2 arg3
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe pack .\target\testapp .\target\testapp_packaged.exe --entry testapp.exe --cache
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\testapp_packaged.exe arg1 arg2 arg3
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\testapp_packaged.exe arg1 arg2 arg3
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe cache list
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe cache clear
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe gc --dry-run
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe pack .\target\testapp .\target\testapp_packaged.exe --entry missing.exe
+    if ($?) { Write-Error 'Command should have failed' }
+
     .\target\onex_bundle_output\onex_x64.exe swap .\target\testapp_packaged.exe --loader .\target\debug\onex_loader.exe --output .\target\testapp_packaged.exe
     if (-not $?) { Write-Error 'Command failed' }
     .\target\testapp_packaged.exe arg1 arg2 arg3
@@ -46,12 +76,25 @@ try {
     .\target\testapp_packaged.exe arg1 arg2 arg3
     if (-not $?) { Write-Error 'Command failed' }
 
+    .\target\onex_bundle_output\onex_x64.exe info .\target\testapp_packaged.exe
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe info --format json .\target\testapp_packaged.exe | ConvertFrom-Json
+    if (-not $?) { Write-Error 'Command failed' }
     .\target\onex_bundle_output\onex_x64.exe list .\target\testapp_packaged.exe
     if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe list --long .\target\testapp_packaged.exe
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe list --tree .\target\testapp_packaged.exe
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe list --format json .\target\testapp_packaged.exe | ConvertFrom-Json | Format-Table
+    if (-not $?) { Write-Error 'Command failed' }
 
     .\target\onex_bundle_output\onex_x64.exe extract .\target\testapp_packaged.exe .\target\extracted
     if (-not $?) { Write-Error 'Command failed' }
     Get-ChildItem -Recurse .\target\extracted
+    .\target\onex_bundle_output\onex_x64.exe extract .\target\testapp_packaged.exe .\target\extracted_exe '*.exe' --fail-if-exists
+    if (-not $?) { Write-Error 'Command failed' }
+    Get-ChildItem -Recurse .\target\extracted_exe
 
     .\target\onex_bundle_output\onex_x64.exe check .\target\testapp_packaged.exe
     if (-not $?) { Write-Error 'Command failed' }
@@ -59,6 +102,20 @@ try {
     if (-not $?) { Write-Error 'Command failed' }
     .\target\onex_bundle_output\onex_x64.exe check .\target\debug\onex.exe
     if ($?) { Write-Error 'Command should have failed' }
+
+    .\target\onex_bundle_output\onex_x64.exe verify .\target\testapp_packaged.exe
+    if (-not $?) { Write-Error 'Command failed' }
+
+    .\target\onex_bundle_output\onex_x64.exe keygen .\target\testapp_key .\target\testapp_key.pub
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe verify .\target\testapp_packaged.exe --pubkey .\target\testapp_key.pub
+    if ($?) { Write-Error 'Command should have failed' }
+    .\target\onex_bundle_output\onex_x64.exe sign .\target\testapp_packaged.exe --key .\target\testapp_key
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\onex_bundle_output\onex_x64.exe verify .\target\testapp_packaged.exe --pubkey .\target\testapp_key.pub
+    if (-not $?) { Write-Error 'Command failed' }
+    .\target\testapp_packaged.exe arg1 arg2 arg3
+    if (-not $?) { Write-Error 'Command failed' }
 } finally {
     Pop-Location
 }
@@ -25,6 +25,10 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         .collect::<Vec<String>>()
         .join(" ");
     println!("Args: {}", arg_string);
+    println!("Working directory: {}", env::current_dir()?.display());
+    for (key, value) in env::vars().filter(|(key, _)| key.starts_with("ONEX_")) {
+        println!("{}={}", key, value);
+    }
 
     println!("Directory contents:");
     let exe_path = env::current_exe()?;
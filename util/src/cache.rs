@@ -0,0 +1,546 @@
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use {
+    crate::{
+        digest::{sha256, to_hex, Digest},
+        manifest::Manifest,
+        result::Result,
+        zip::{extract_zip, list_entries, ArchiveEntry, ArchiveEntryKind, UnsafePathPolicy},
+    },
+    filetime::FileTime,
+    fs2::FileExt,
+    log::trace,
+    serde::{Deserialize, Serialize},
+    std::{
+        cmp::Reverse,
+        collections::BTreeMap,
+        env,
+        fs::{self, File, OpenOptions},
+        io::{self, Read, Seek, SeekFrom},
+        path::{Path, PathBuf},
+        time::SystemTime,
+    },
+    walkdir::WalkDir,
+};
+
+/// Overrides where apps are cached.
+pub const CACHE_DIR_VAR: &str = "ONEX_CACHE_DIR";
+
+/// Where an entry's extracted app lives, inside the entry's directory.
+const APP_DIR: &str = "app";
+/// Written once an entry's app has been fully extracted. Its modification time
+/// is when the entry was last used.
+const RECORD_FILE: &str = "onex_cache.toml";
+/// Locked shared by every app running from an entry, and exclusively while
+/// the entry is being extracted or removed.
+const LOCK_FILE: &str = "lock";
+
+/// How many times to extract an app before giving up on someone else
+/// removing it from under us.
+const MAX_EXTRACT_ATTEMPTS: usize = 3;
+
+/// What a cache entry holds, for `onex cache list`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheRecord {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub entry: String,
+    /// Each extracted file, by its path in the app.
+    #[serde(default)]
+    pub files: BTreeMap<String, CachedFile>,
+}
+
+/// What an extracted file looked like right after extraction. The size and
+/// modification time are checked every time the entry is used, and the
+/// digest only by `AppCache::verify`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedFile {
+    pub size: u64,
+    pub modified: i64,
+    #[serde(default)]
+    pub modified_nanos: u32,
+    /// The hex-encoded SHA-256 digest of the file.
+    pub sha256: String,
+}
+
+impl CachedFile {
+    fn read(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = FileTime::from_last_modification_time(&metadata);
+        Ok(CachedFile {
+            size: metadata.len(),
+            modified: modified.unix_seconds(),
+            modified_nanos: modified.nanoseconds(),
+            sha256: file_digest(path)?,
+        })
+    }
+
+    /// Whether the file at `path` still has the recorded size and
+    /// modification time.
+    fn looks_unchanged(&self, path: &Path) -> bool {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) => {
+                let modified = FileTime::from_last_modification_time(&metadata);
+                metadata.is_file()
+                    && metadata.len() == self.size
+                    && modified.unix_seconds() == self.modified
+                    && modified.nanoseconds() == self.modified_nanos
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// A per-user directory of extracted apps, keyed by the SHA-256 digest of
+/// their payload, which the loader runs apps from instead of extracting them
+/// on every launch.
+pub struct AppCache {
+    root: PathBuf,
+}
+
+impl AppCache {
+    pub fn new(root: PathBuf) -> Self {
+        AppCache { root }
+    }
+
+    /// The cache in `$ONEX_CACHE_DIR`, or else in the user's cache directory.
+    pub fn user() -> Result<Self> {
+        let root = match env::var_os(CACHE_DIR_VAR) {
+            Some(dir) => PathBuf::from(dir),
+            None => match dirs::cache_dir() {
+                Some(dir) => dir.join("onex"),
+                None => {
+                    return Err(format!(
+                        "There is no cache directory for this user; set {} to choose one.",
+                        CACHE_DIR_VAR
+                    )
+                    .into())
+                }
+            },
+        };
+        Ok(AppCache::new(root))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the cached copy of the app in `payload`, extracting it first if
+    /// it isn't cached or the cached copy is incomplete. The copy stays in the
+    /// cache for as long as the returned `CachedApp` is alive.
+    ///
+    /// `payload_digest` is the digest the app records for its payload, which
+    /// saves hashing the whole payload on every launch. It's checked against
+    /// the payload whenever the app is extracted, so an app can only ever fill
+    /// an entry with a payload matching the entry's key.
+    pub fn open<S: Read + Seek>(
+        &self,
+        mut payload: S,
+        payload_digest: Option<Digest>,
+        manifest: &Manifest,
+    ) -> Result<CachedApp> {
+        let key = match payload_digest {
+            Some(digest) => to_hex(&digest),
+            None => {
+                payload.seek(SeekFrom::Start(0))?;
+                to_hex(&sha256(&mut payload)?)
+            }
+        };
+        let entries = list_entries(&mut payload)?;
+        let dir = self.root.join(&key);
+
+        for _ in 0..MAX_EXTRACT_ATTEMPTS {
+            fs::create_dir_all(&dir)?;
+            let lock = open_lock(&dir)?;
+            lock.lock_shared()?;
+            if is_complete(&dir, &entries) {
+                filetime::set_file_mtime(dir.join(RECORD_FILE), FileTime::now())?;
+                return Ok(CachedApp {
+                    path: dir.join(APP_DIR),
+                    _lock: lock,
+                });
+            }
+
+            lock.unlock()?;
+            lock.lock_exclusive()?;
+            if !is_complete(&dir, &entries) {
+                trace!("Extracting {} to {}", key, dir.display());
+                if payload_digest.is_some() {
+                    payload.seek(SeekFrom::Start(0))?;
+                    if to_hex(&sha256(&mut payload)?) != key {
+                        return Err("The app's payload doesn't match its recorded digest.".into());
+                    }
+                }
+                remove_contents(&dir)?;
+                extract_zip(&mut payload, &dir.join(APP_DIR), UnsafePathPolicy::Reject)?;
+                let mut files = BTreeMap::new();
+                for entry in entries.iter() {
+                    if entry.kind == ArchiveEntryKind::File {
+                        let file = CachedFile::read(&dir.join(APP_DIR).join(&entry.path))?;
+                        files.insert(entry.path.clone(), file);
+                    }
+                }
+                let record = CacheRecord {
+                    name: manifest.name.clone(),
+                    version: manifest.version.clone(),
+                    entry: manifest.entry.clone(),
+                    files,
+                };
+                fs::write(dir.join(RECORD_FILE), toml::to_string(&record)?)?;
+            }
+            // Let go to take the shared lock, making sure the entry wasn't
+            // removed in between.
+            lock.unlock()?;
+        }
+
+        Err(format!(
+            "The cached copy of the app in {} keeps being removed.",
+            dir.display()
+        )
+        .into())
+    }
+
+    /// Describes every entry in the cache, including any left incomplete by an
+    /// interrupted extraction, most recently used first.
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let read_dir = match fs::read_dir(&self.root) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            result => result?,
+        };
+
+        let mut entries = Vec::new();
+        for dir_entry in read_dir {
+            let dir_entry = dir_entry?;
+            if !dir_entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let path = dir_entry.path();
+            let record_path = path.join(RECORD_FILE);
+            let record = read_record(&path);
+            let last_used = fs::metadata(&record_path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            let size = WalkDir::new(&path)
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.metadata().ok())
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| metadata.len())
+                .sum();
+            entries.push(CacheEntry {
+                key: dir_entry.file_name().to_string_lossy().into_owned(),
+                in_use: is_in_use(&path)?,
+                path,
+                record,
+                size,
+                last_used,
+            });
+        }
+
+        entries.sort_by_key(|entry| Reverse(entry.last_used));
+        Ok(entries)
+    }
+
+    /// Re-hashes every file in the entry with the given key, returning whether
+    /// they all still match what was extracted. Entries that were never fully
+    /// extracted don't match.
+    pub fn verify(&self, key: &str) -> Result<bool> {
+        let dir = self.root.join(key);
+        let lock = open_lock(&dir)?;
+        lock.lock_shared()?;
+        let record = match read_record(&dir) {
+            Some(record) => record,
+            None => return Ok(false),
+        };
+
+        let app_dir = dir.join(APP_DIR);
+        Ok(record.files.iter().all(|(path, file)| {
+            matches!(file_digest(&app_dir.join(path)), Ok(digest) if digest == file.sha256)
+        }))
+    }
+
+    /// Removes the entry with the given key, unless an app is running from it.
+    /// Returns whether it was removed.
+    pub fn remove(&self, key: &str) -> Result<bool> {
+        let dir = self.root.join(key);
+        if !dir.is_dir() {
+            return Ok(true);
+        }
+
+        {
+            let lock = open_lock(&dir)?;
+            match lock.try_lock_exclusive() {
+                Err(err) if err.kind() == fs2::lock_contended_error().kind() => return Ok(false),
+                result => result?,
+            }
+            remove_contents(&dir)?;
+        }
+        // The lock file is only removed once it's closed, since Windows won't
+        // remove a directory with an open file in it.
+        force_remove_dir_all(&dir)?;
+        Ok(true)
+    }
+}
+
+/// An app extracted to the cache, which can't be removed from it while this
+/// is alive.
+pub struct CachedApp {
+    path: PathBuf,
+    _lock: File,
+}
+
+impl CachedApp {
+    /// The root of the extracted app.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// One app in the cache.
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    /// The hex-encoded SHA-256 digest of the app's payload.
+    pub key: String,
+    pub path: PathBuf,
+    /// What the entry holds, or `None` if it was never fully extracted.
+    pub record: Option<CacheRecord>,
+    /// The size of every file in the entry, in bytes.
+    pub size: u64,
+    pub last_used: Option<SystemTime>,
+    /// Whether an app is running from the entry.
+    pub in_use: bool,
+}
+
+/// Like `fs::remove_dir_all`, but also removes the read-only files and
+/// directories apps can come with.
+pub fn force_remove_dir_all(path: &Path) -> io::Result<()> {
+    fs::remove_dir_all(path).or_else(|_| {
+        make_writable(path)?;
+        fs::remove_dir_all(path)
+    })
+}
+
+fn make_writable(path: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    let mut permissions = metadata.permissions();
+    #[cfg(unix)]
+    {
+        if metadata.is_dir() && permissions.mode() & 0o700 != 0o700 {
+            permissions.set_mode(permissions.mode() | 0o700);
+            fs::set_permissions(path, permissions)?;
+        }
+    }
+    #[cfg(windows)]
+    {
+        if permissions.readonly() {
+            permissions.set_readonly(false);
+            fs::set_permissions(path, permissions)?;
+        }
+    }
+
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            make_writable(&entry?.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn open_lock(dir: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(LOCK_FILE))
+}
+
+fn is_in_use(dir: &Path) -> Result<bool> {
+    let lock = match OpenOptions::new().read(true).open(dir.join(LOCK_FILE)) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        result => result?,
+    };
+    match lock.try_lock_exclusive() {
+        Err(err) if err.kind() == fs2::lock_contended_error().kind() => Ok(true),
+        result => {
+            result?;
+            lock.unlock()?;
+            Ok(false)
+        }
+    }
+}
+
+fn read_record(dir: &Path) -> Option<CacheRecord> {
+    fs::read_to_string(dir.join(RECORD_FILE))
+        .ok()
+        .and_then(|s| toml::from_str(&s).ok())
+}
+
+/// Whether the entry was fully extracted and all its files are still there,
+/// with the size and modification time they were extracted with.
+fn is_complete(dir: &Path, entries: &[ArchiveEntry]) -> bool {
+    let record = match read_record(dir) {
+        Some(record) => record,
+        None => return false,
+    };
+
+    let app_dir = dir.join(APP_DIR);
+    entries.iter().all(|entry| {
+        let path = app_dir.join(&entry.path);
+        match entry.kind {
+            ArchiveEntryKind::File => match record.files.get(&entry.path) {
+                Some(file) => file.size == entry.size && file.looks_unchanged(&path),
+                None => false,
+            },
+            ArchiveEntryKind::Directory | ArchiveEntryKind::Symlink(_) => {
+                fs::symlink_metadata(&path).is_ok()
+            }
+        }
+    })
+}
+
+fn file_digest(path: &Path) -> Result<String> {
+    Ok(to_hex(&sha256(&mut File::open(path)?)?))
+}
+
+/// Removes the record, then the extracted app, so an entry is never left
+/// looking complete when it isn't.
+fn remove_contents(dir: &Path) -> io::Result<()> {
+    match fs::remove_file(dir.join(RECORD_FILE)) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        result => result?,
+    }
+    let app_dir = dir.join(APP_DIR);
+    if app_dir.exists() {
+        force_remove_dir_all(&app_dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{misc::SeekableVec, zip::zip_app_dir},
+    };
+
+    #[test]
+    fn open_extracts_once_and_revalidates() {
+        let temp_dir = env::temp_dir().join("onex_open_extracts_once_and_revalidates");
+        let app_dir = temp_dir.join("app");
+        fs::create_dir_all(app_dir.join("bin")).unwrap();
+        fs::write(app_dir.join("bin").join("app.exe"), b"app").unwrap();
+        let payload = zip_app_dir(&app_dir, None).unwrap();
+        let manifest = Manifest::new("bin/app.exe".to_owned());
+        let cache = AppCache::new(temp_dir.join("cache"));
+
+        let cached_file = {
+            let cached = cache
+                .open(SeekableVec::new(payload.clone()), None, &manifest)
+                .unwrap();
+            let cached_file = cached.path().join("bin").join("app.exe");
+            assert_eq!(fs::read(&cached_file).unwrap(), b"app");
+            cached_file
+        };
+        cache
+            .open(SeekableVec::new(payload.clone()), None, &manifest)
+            .unwrap();
+        assert_eq!(fs::read(&cached_file).unwrap(), b"app");
+
+        // Changed files are extracted again, even if their size is the same.
+        fs::write(&cached_file, b"ppa").unwrap();
+        filetime::set_file_mtime(&cached_file, FileTime::from_unix_time(1, 0)).unwrap();
+        cache
+            .open(SeekableVec::new(payload.clone()), None, &manifest)
+            .unwrap();
+        assert_eq!(fs::read(&cached_file).unwrap(), b"app");
+
+        fs::write(&cached_file, b"truncated").unwrap();
+        cache
+            .open(SeekableVec::new(payload.clone()), None, &manifest)
+            .unwrap();
+        assert_eq!(fs::read(&cached_file).unwrap(), b"app");
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn open_trusts_the_recorded_digest_until_extracting() {
+        let temp_dir = env::temp_dir().join("onex_open_trusts_the_recorded_digest");
+        let app_dir = temp_dir.join("app");
+        fs::create_dir_all(&app_dir).unwrap();
+        fs::write(app_dir.join("app.exe"), b"app").unwrap();
+        let payload = zip_app_dir(&app_dir, None).unwrap();
+        let digest = sha256(&mut payload.as_slice()).unwrap();
+        let manifest = Manifest::new("app.exe".to_owned());
+        let cache = AppCache::new(temp_dir.join("cache"));
+
+        let other_digest = [7; 32];
+        assert!(cache
+            .open(
+                SeekableVec::new(payload.clone()),
+                Some(other_digest),
+                &manifest
+            )
+            .is_err());
+
+        let cached_file = cache
+            .open(SeekableVec::new(payload.clone()), Some(digest), &manifest)
+            .unwrap()
+            .path()
+            .join("app.exe");
+        let key = to_hex(&digest);
+        assert_eq!(cache.entries().unwrap()[0].key, key);
+        assert!(cache.verify(&key).unwrap());
+
+        // Changes that keep the size and modification time are only caught
+        // by verifying.
+        let modified = FileTime::from_last_modification_time(&fs::metadata(&cached_file).unwrap());
+        fs::write(&cached_file, b"ppa").unwrap();
+        filetime::set_file_mtime(&cached_file, modified).unwrap();
+        cache
+            .open(SeekableVec::new(payload.clone()), Some(digest), &manifest)
+            .unwrap();
+        assert_eq!(fs::read(&cached_file).unwrap(), b"ppa");
+        assert!(!cache.verify(&key).unwrap());
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn entries_in_use_are_kept() {
+        let temp_dir = env::temp_dir().join("onex_entries_in_use_are_kept");
+        let app_dir = temp_dir.join("app");
+        fs::create_dir_all(&app_dir).unwrap();
+        fs::write(app_dir.join("app.exe"), b"app").unwrap();
+        let payload = zip_app_dir(&app_dir, None).unwrap();
+        let mut manifest = Manifest::new("app.exe".to_owned());
+        manifest.name = Some("app".to_owned());
+        let cache = AppCache::new(temp_dir.join("cache"));
+        assert!(cache.entries().unwrap().is_empty());
+
+        let cached = cache
+            .open(SeekableVec::new(payload), None, &manifest)
+            .unwrap();
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].record.as_ref().and_then(|r| r.name.as_deref()),
+            Some("app")
+        );
+        assert!(entries[0].size >= 3);
+        assert!(entries[0].last_used.is_some());
+        assert!(entries[0].in_use);
+        assert!(!cache.remove(&entries[0].key).unwrap());
+
+        drop(cached);
+        assert!(!cache.entries().unwrap()[0].in_use);
+        assert!(cache.remove(&entries[0].key).unwrap());
+        assert!(cache.entries().unwrap().is_empty());
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+}
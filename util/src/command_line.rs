@@ -0,0 +1,212 @@
+use crate::result::Result;
+
+/// Builds a command line that runs `program` with exactly `args`. Windows
+/// passes a process its arguments as this one string, which the C runtime and
+/// `CommandLineToArgvW` split back up following the rules in
+/// https://docs.microsoft.com/en-us/cpp/c-language/parsing-c-command-line-arguments.
+pub fn build_command_line<S: AsRef<str>>(program: &str, args: &[S]) -> Result<String> {
+    // The program name ends at the next quote or, outside quotes, whitespace,
+    // with no way to escape either.
+    if program.contains('"') {
+        return Err(format!("The program path '{}' contains a quote.", program).into());
+    }
+
+    let mut command_line = if program.is_empty() || program.contains(is_separator) {
+        format!("\"{}\"", program)
+    } else {
+        program.to_owned()
+    };
+    for arg in args {
+        command_line.push(' ');
+        command_line.push_str(&quote_arg(arg.as_ref()));
+    }
+    Ok(command_line)
+}
+
+/// Quotes `arg` so it's read back as a single argument, leaving it alone if
+/// it doesn't need quoting.
+pub fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c| is_separator(c) || c == '"') {
+        return arg.to_owned();
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                // Backslashes only escape when they come before a quote, so
+                // they're doubled along with one more for the quote itself.
+                push_backslashes(&mut quoted, backslashes * 2 + 1);
+                backslashes = 0;
+            }
+            _ => {
+                push_backslashes(&mut quoted, backslashes);
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            quoted.push(c);
+        }
+    }
+    // The closing quote would otherwise be escaped by any trailing backslashes.
+    push_backslashes(&mut quoted, backslashes * 2);
+    quoted.push('"');
+    quoted
+}
+
+/// Splits a command line into the program and its arguments, as the C runtime
+/// does before calling `main`.
+pub fn parse_command_line(command_line: &str) -> Vec<String> {
+    let mut chars = command_line.chars().peekable();
+    let mut args = Vec::new();
+
+    let mut program = String::new();
+    let mut in_quotes = false;
+    for c in chars.by_ref() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if is_separator(c) && !in_quotes => break,
+            c => program.push(c),
+        }
+    }
+    args.push(program);
+
+    loop {
+        while matches!(chars.peek(), Some(&c) if is_separator(c)) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut arg = String::new();
+        let mut in_quotes = false;
+        while let Some(&c) = chars.peek() {
+            match c {
+                '\\' => {
+                    let mut backslashes = 0;
+                    while chars.peek() == Some(&'\\') {
+                        chars.next();
+                        backslashes += 1;
+                    }
+                    if chars.peek() == Some(&'"') {
+                        push_backslashes(&mut arg, backslashes / 2);
+                        if backslashes % 2 == 1 {
+                            chars.next();
+                            arg.push('"');
+                        }
+                    } else {
+                        push_backslashes(&mut arg, backslashes);
+                    }
+                }
+                '"' => {
+                    chars.next();
+                    // A doubled quote inside quotes is a literal quote.
+                    if in_quotes && chars.peek() == Some(&'"') {
+                        chars.next();
+                        arg.push('"');
+                    } else {
+                        in_quotes = !in_quotes;
+                    }
+                }
+                c if is_separator(c) && !in_quotes => break,
+                c => {
+                    chars.next();
+                    arg.push(c);
+                }
+            }
+        }
+        args.push(arg);
+    }
+
+    args
+}
+
+fn is_separator(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+fn push_backslashes(s: &mut String, count: usize) {
+    for _ in 0..count {
+        s.push('\\');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng},
+    };
+
+    #[test]
+    fn quotes_only_when_needed() {
+        assert_eq!(quote_arg("plain"), "plain");
+        assert_eq!(quote_arg(r"C:\dir\file"), r"C:\dir\file");
+        assert_eq!(quote_arg(""), r#""""#);
+        assert_eq!(quote_arg("two words"), r#""two words""#);
+        assert_eq!(quote_arg(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote_arg(r#"a\"b"#), r#""a\\\"b""#);
+        assert_eq!(quote_arg(r"C:\with space\"), r#""C:\with space\\""#);
+    }
+
+    #[test]
+    fn builds_command_lines() {
+        assert_eq!(
+            build_command_line(r"C:\Program Files\app.exe", &["a b", "c"]).unwrap(),
+            r#""C:\Program Files\app.exe" "a b" c"#
+        );
+        assert_eq!(
+            build_command_line::<&str>(r"C:\app.exe", &[]).unwrap(),
+            r"C:\app.exe"
+        );
+        assert!(build_command_line::<&str>(r#"C:\"app".exe"#, &[]).is_err());
+    }
+
+    #[test]
+    fn parses_documented_examples() {
+        let parse = |args: &str| parse_command_line(&format!("app.exe {}", args))[1..].to_vec();
+        assert_eq!(parse(r#""a b c" d e"#), vec!["a b c", "d", "e"]);
+        assert_eq!(parse(r#""ab\"c" "\\" d"#), vec![r#"ab"c"#, r"\", "d"]);
+        assert_eq!(parse(r#"a\\\b d"e f"g h"#), vec![r"a\\\b", "de fg", "h"]);
+        assert_eq!(parse(r#"a\\\"b c d"#), vec![r#"a\"b"#, "c", "d"]);
+        assert_eq!(parse(r#"a\\\\"b c" d e"#), vec![r"a\\b c", "d", "e"]);
+        assert_eq!(parse(r#"a"b"" c d"#), vec![r#"ab" c d"#]);
+        assert_eq!(
+            parse_command_line(r#""C:\Program Files\app.exe"  """#),
+            vec![r"C:\Program Files\app.exe", ""]
+        );
+    }
+
+    #[test]
+    fn command_lines_round_trip() {
+        const ALPHABET: &[char] = &[
+            'a', 'Z', '0', ' ', '\t', '"', '\\', '\'', '^', '%', 'é', '日',
+        ];
+        let mut rng = StdRng::seed_from_u64(0x6f6e6578);
+
+        for _ in 0..10_000 {
+            let mut random_string = |max_length: usize| {
+                let length = rng.gen_range(0, max_length + 1);
+                (0..length)
+                    .map(|_| *ALPHABET.choose(&mut rng).unwrap())
+                    .collect::<String>()
+            };
+            let program = random_string(8).replace('"', "");
+            let args = (0..4).map(|_| random_string(8)).collect::<Vec<_>>();
+
+            let command_line = build_command_line(&program, &args).unwrap();
+            let mut expected = vec![program];
+            expected.extend(args);
+            assert_eq!(
+                parse_command_line(&command_line),
+                expected,
+                "{}",
+                command_line
+            );
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use {
+    crate::result::Result,
+    sha2::{Digest as _, Sha256},
+    std::{
+        fmt::Write as _,
+        io::{self, Read},
+    },
+};
+
+pub const DIGEST_LENGTH: usize = 32;
+
+pub type Digest = [u8; DIGEST_LENGTH];
+
+pub fn sha256<R: Read>(r: &mut R) -> Result<Digest> {
+    let mut hasher = Sha256::new();
+    io::copy(r, &mut hasher)?;
+
+    let mut digest = [0; DIGEST_LENGTH];
+    digest.copy_from_slice(&hasher.finalize());
+    Ok(digest)
+}
+
+pub fn sha256_bytes(bytes: &[u8]) -> Digest {
+    let mut digest = [0; DIGEST_LENGTH];
+    digest.copy_from_slice(&Sha256::digest(bytes));
+    digest
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(s, "{:02x}", byte).unwrap();
+    }
+    s
+}
+
+pub fn from_hex(s: &str) -> Result<Vec<u8>> {
+    let s = s.trim();
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return Err(format!("'{}' is not a valid hex string.", s).into());
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&s[i..i + 2], 16)
+                .map_err(|_| format!("'{}' is not a valid hex string.", s).into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_known_value() {
+        assert_eq!(
+            to_hex(&sha256(&mut &b"abc"[..]).unwrap()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!(from_hex("00ff7a\n").unwrap(), vec![0x00, 0xff, 0x7a]);
+        assert_eq!(to_hex(&[0x00, 0xff, 0x7a]), "00ff7a");
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+}
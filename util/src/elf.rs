@@ -0,0 +1,115 @@
+use {
+    crate::result::{Error, Result},
+    std::io::{self, Read, Seek, SeekFrom},
+};
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+/// The identification bytes, followed by the type and the machine, which sit
+/// in the same place in 32 and 64-bit files.
+const HEADER_PREFIX_LENGTH: usize = 20;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const DATA_BIG_ENDIAN: u8 = 2;
+
+/// An executable that isn't position independent.
+const ET_EXEC: u16 = 2;
+/// A position independent executable or a shared library, which can't be told
+/// apart from the header alone.
+const ET_DYN: u16 = 3;
+
+/// The parts of an ELF file's header onex cares about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ElfHeader {
+    /// The `ET_*` type of the file.
+    pub file_type: u16,
+    /// The `EM_*` machine the file is built for.
+    pub machine: u16,
+}
+
+impl ElfHeader {
+    /// Reads the header of the ELF file at the start of `r`.
+    pub fn read<R: Read + Seek>(r: &mut R) -> Result<Self> {
+        let mut bytes = [0; HEADER_PREFIX_LENGTH];
+        r.seek(SeekFrom::Start(0))?;
+        match r.read_exact(&mut bytes) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Err(not_elf()),
+            result => result?,
+        }
+        if &bytes[..4] != ELF_MAGIC {
+            return Err(not_elf());
+        }
+
+        let u16_at = |offset: usize| {
+            let field = [bytes[offset], bytes[offset + 1]];
+            match bytes[5] {
+                DATA_LITTLE_ENDIAN => Ok(u16::from_le_bytes(field)),
+                DATA_BIG_ENDIAN => Ok(u16::from_be_bytes(field)),
+                _ => Err(not_elf()),
+            }
+        };
+        Ok(ElfHeader {
+            file_type: u16_at(16)?,
+            machine: u16_at(18)?,
+        })
+    }
+
+    /// Whether the file might be a program that can be started, rather than an
+    /// object file or a core dump.
+    pub fn is_executable(&self) -> bool {
+        self.file_type == ET_EXEC || self.file_type == ET_DYN
+    }
+
+    /// The name Rust gives the machine's architecture, as in
+    /// `std::env::consts::ARCH`.
+    pub fn arch(&self) -> Option<&'static str> {
+        match self.machine {
+            0x03 => Some("x86"),
+            0x3e => Some("x86_64"),
+            0xb7 => Some("aarch64"),
+            _ => None,
+        }
+    }
+}
+
+fn not_elf() -> Error {
+    "This is not an ELF file.".into()
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::io::Cursor};
+
+    fn elf_bytes(data: u8, file_type: u16, machine: u16) -> Vec<u8> {
+        let mut bytes = vec![0; 64];
+        bytes[..4].copy_from_slice(ELF_MAGIC);
+        bytes[4] = 2;
+        bytes[5] = data;
+        let (file_type, machine) = if data == DATA_BIG_ENDIAN {
+            (file_type.to_be_bytes(), machine.to_be_bytes())
+        } else {
+            (file_type.to_le_bytes(), machine.to_le_bytes())
+        };
+        bytes[16..18].copy_from_slice(&file_type);
+        bytes[18..20].copy_from_slice(&machine);
+        bytes
+    }
+
+    #[test]
+    fn reads_header() {
+        let header = ElfHeader::read(&mut Cursor::new(elf_bytes(
+            DATA_LITTLE_ENDIAN,
+            ET_DYN,
+            0x3e,
+        )))
+        .unwrap();
+        assert_eq!(header.arch(), Some("x86_64"));
+        assert!(header.is_executable());
+
+        let header =
+            ElfHeader::read(&mut Cursor::new(elf_bytes(DATA_BIG_ENDIAN, 1, 0xb7))).unwrap();
+        assert_eq!(header.arch(), Some("aarch64"));
+        assert!(!header.is_executable());
+
+        assert!(ElfHeader::read(&mut Cursor::new(b"MZ".to_vec())).is_err());
+        assert!(ElfHeader::read(&mut Cursor::new(elf_bytes(0, ET_EXEC, 0x3e))).is_err());
+    }
+}
@@ -1,52 +1,276 @@
 use {
-    crate::{misc::OffsetSeeker, result::Result},
+    crate::{
+        digest::{sha256, sha256_bytes, to_hex, Digest, DIGEST_LENGTH},
+        manifest::{Manifest, LEGACY_ENTRY_FILE},
+        misc::OffsetSeeker,
+        pe::{self, PeHeaders},
+        result::Result,
+        section::{self, Section, SectionId},
+        signature::{self, PayloadSignature, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH},
+    },
     std::{
+        convert::TryInto,
         fs::File,
-        io::{Read, Seek, SeekFrom},
+        io::{Cursor, Read, Seek, SeekFrom},
     },
+    zip::{result::ZipError, ZipArchive},
 };
 
-const DATA_OFFSET_LENGTH: usize = 8;
-const SIGNATURE: &str = "ONEX";
+const MAGIC: &[u8; 4] = b"ONEX";
+
+/// The container format this build writes. Besides it, only version 0 files
+/// can be read.
+///
+/// Version 2 holds any number of sections between the loader and the trailer,
+/// followed by a directory describing them.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Version 0 files end with just the 8-byte data offset and the magic. Its
+/// upper four bytes sit where later versions store the format version, so a
+/// version 0 file reads as version 0 as long as the loader is under 4 GiB.
+const LEGACY_TRAILER_LENGTH: u32 = 12;
+const TRAILER_LENGTH: u32 = 32;
+
+/// The trailer is preceded by the SHA-256 digest of the loader.
+pub const FLAG_LOADER_DIGEST: u32 = 0x2;
+
+const SIGNATURE_FIELD_LENGTH: u32 = (PUBLIC_KEY_LENGTH + SIGNATURE_LENGTH) as u32;
+
+/// The fixed-size record at the very end of every onex app.
+///
+/// It is laid out, from the end of the file backwards, as the magic, the
+/// format version, the length of the whole trailer, the flags, the length of
+/// the data and finally the offset of the data, which is where the loader
+/// ends. All integers are little endian. Optional fields selected by the flags
+/// come before all of that, and are included in the header length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trailer {
+    pub format_version: u32,
+    pub header_length: u32,
+    pub flags: u32,
+    pub data_length: u64,
+    pub data_offset: u64,
+    pub loader_digest: Option<Digest>,
+}
+
+impl Trailer {
+    pub(crate) fn new(data_offset: u64, data_length: u64, loader_digest: Option<Digest>) -> Self {
+        let mut flags = 0;
+        let mut header_length = TRAILER_LENGTH;
+        if loader_digest.is_some() {
+            flags |= FLAG_LOADER_DIGEST;
+            header_length += DIGEST_LENGTH as u32;
+        }
+
+        Trailer {
+            format_version: FORMAT_VERSION,
+            header_length,
+            flags,
+            data_length,
+            data_offset,
+            loader_digest,
+        }
+    }
+
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.header_length as usize);
+        if let Some(loader_digest) = &self.loader_digest {
+            bytes.extend(loader_digest);
+        }
+        bytes.extend(&self.data_offset.to_le_bytes());
+        bytes.extend(&self.data_length.to_le_bytes());
+        bytes.extend(&self.flags.to_le_bytes());
+        bytes.extend(&self.header_length.to_le_bytes());
+        bytes.extend(&self.format_version.to_le_bytes());
+        bytes.extend(MAGIC);
+
+        debug_assert_eq!(bytes.len(), self.header_length as usize);
+        bytes
+    }
+
+    fn read<R: Read + Seek>(r: &mut R) -> Result<Self> {
+        let end = trailer_end(r)?;
+        if end < 8 {
+            return Err("Signature not found for executable file.".into());
+        }
+
+        let tail = read_from_end(r, end, 8)?;
+        if &tail[4..] != MAGIC {
+            return Err("Signature not found for executable file.".into());
+        }
+
+        let format_version = u32_at(&tail, 0);
+        if format_version == 0 {
+            return Self::read_legacy(r, end);
+        } else if format_version != FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported onex format version {} (this build supports versions 0 and {}).",
+                format_version, FORMAT_VERSION
+            )
+            .into());
+        }
+
+        if end < TRAILER_LENGTH as u64 {
+            return Err("The onex trailer is truncated.".into());
+        }
+        let bytes = read_from_end(r, end, TRAILER_LENGTH)?;
+        let mut trailer = Trailer {
+            format_version,
+            header_length: u32_at(&bytes, 20),
+            flags: u32_at(&bytes, 16),
+            data_length: u64_at(&bytes, 8),
+            data_offset: u64_at(&bytes, 0),
+            loader_digest: None,
+        };
+
+        if trailer.header_length < TRAILER_LENGTH || trailer.header_length as u64 > end {
+            return Err("The onex trailer is corrupt.".into());
+        }
+        let data_end = end - trailer.header_length as u64;
+        if trailer.data_offset > data_end || trailer.data_length > data_end - trailer.data_offset {
+            return Err("The onex trailer is corrupt.".into());
+        }
+
+        if trailer.flags & FLAG_LOADER_DIGEST != 0 {
+            let field_offset = TRAILER_LENGTH + DIGEST_LENGTH as u32;
+            let bytes = read_field(r, end, field_offset, DIGEST_LENGTH, &trailer)?;
+            trailer.loader_digest = Some(bytes[..].try_into().unwrap());
+        }
+
+        Ok(trailer)
+    }
+
+    fn read_legacy<R: Read + Seek>(r: &mut R, end: u64) -> Result<Self> {
+        if end < LEGACY_TRAILER_LENGTH as u64 {
+            return Err("The onex trailer is truncated.".into());
+        }
+        let bytes = read_from_end(r, end, LEGACY_TRAILER_LENGTH)?;
+        let data_offset = u64_at(&bytes, 0);
+
+        let data_end = end - LEGACY_TRAILER_LENGTH as u64;
+        if data_offset > data_end {
+            return Err("The onex trailer is corrupt.".into());
+        }
+
+        Ok(Trailer {
+            format_version: 0,
+            header_length: LEGACY_TRAILER_LENGTH,
+            flags: 0,
+            data_length: data_end - data_offset,
+            data_offset,
+            loader_digest: None,
+        })
+    }
+
+    fn data_end(&self) -> u64 {
+        self.data_offset + self.data_length
+    }
+}
+
+/// Bytes that should replace everything from `offset` to the end of an app.
+pub struct TailPatch {
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
 
-pub struct OnexFile {
-    f: File,
+/// An onex app read from a file, or from anything else that can be read and
+/// seeked like one.
+pub struct OnexFile<R: Read + Seek = File> {
+    f: R,
+    trailer: Trailer,
+    sections: Vec<Section>,
 }
 
 impl OnexFile {
-    pub fn new(mut f: File) -> Result<Self> {
-        Self::validate(&mut f)?;
-        Ok(OnexFile { f })
+    pub fn generate_bytes(
+        loader_bytes: Vec<u8>,
+        data_bytes: Vec<u8>,
+        digest_loader: bool,
+    ) -> Result<Vec<u8>> {
+        Self::generate_sections_bytes(
+            loader_bytes,
+            vec![(SectionId::Payload, data_bytes)],
+            digest_loader,
+        )
     }
 
-    pub fn generate_bytes(loader_bytes: Vec<u8>, data_bytes: Vec<u8>) -> Vec<u8> {
+    /// Lays out the loader followed by each section in order, the section
+    /// directory and the trailer.
+    pub fn generate_sections_bytes(
+        loader_bytes: Vec<u8>,
+        contents: Vec<(SectionId, Vec<u8>)>,
+        digest_loader: bool,
+    ) -> Result<Vec<u8>> {
         let data_offset = loader_bytes.len() as u64;
+        let sections = section::layout_sections(data_offset, &contents);
+        let directory = section::directory_bytes(&sections, data_offset);
 
-        let mut bytes = Vec::with_capacity(
-            loader_bytes.len() + data_bytes.len() + DATA_OFFSET_LENGTH + SIGNATURE.len(),
+        let loader_digest = if digest_loader {
+            Some(pe::image_digest(
+                &mut Cursor::new(&loader_bytes),
+                data_offset,
+            )?)
+        } else {
+            None
+        };
+        let sections_length: u64 = sections.iter().map(|s| s.length).sum();
+        let trailer = Trailer::new(
+            data_offset,
+            sections_length + directory.len() as u64,
+            loader_digest,
         );
+
+        let mut bytes =
+            Vec::with_capacity((trailer.data_end() + trailer.header_length as u64) as usize);
         bytes.extend(loader_bytes);
-        bytes.extend(data_bytes);
+        for (_, section_bytes) in contents {
+            bytes.extend(section_bytes);
+        }
+        bytes.extend(directory);
+        bytes.extend(trailer.to_bytes());
 
-        let data_offset_bytes = data_offset.to_le_bytes();
-        bytes.extend(&data_offset_bytes);
+        debug_assert_eq!(bytes.len(), bytes.capacity());
 
-        bytes.extend(SIGNATURE.bytes());
+        Ok(bytes)
+    }
 
-        debug_assert_eq!(bytes.len(), bytes.capacity());
+    pub fn validate<R: Read + Seek>(r: &mut R) -> Result<()> {
+        let trailer = Trailer::read(r)?;
+        read_sections(r, &trailer)?;
+        Ok(())
+    }
+}
 
-        bytes
+impl<R: Read + Seek> OnexFile<R> {
+    pub fn new(mut f: R) -> Result<Self> {
+        let trailer = Trailer::read(&mut f)?;
+        let sections = read_sections(&mut f, &trailer)?;
+        Ok(OnexFile {
+            f,
+            trailer,
+            sections,
+        })
     }
 
-    pub fn data_offset(&mut self) -> Result<u64> {
-        self.f.seek(SeekFrom::End(
-            -((SIGNATURE.len() + DATA_OFFSET_LENGTH) as i64),
-        ))?;
+    pub fn trailer(&self) -> &Trailer {
+        &self.trailer
+    }
 
-        let mut data_offset = [0; DATA_OFFSET_LENGTH];
-        self.f.read_exact(&mut data_offset)?;
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
 
-        Ok(u64::from_le_bytes(data_offset))
+    /// The digest recorded for the payload, which apps written before sections
+    /// were added don't have.
+    pub fn payload_digest(&self) -> Option<Digest> {
+        self.sections
+            .iter()
+            .find(|s| s.id == SectionId::Payload)
+            .and_then(|s| s.digest)
+    }
+
+    pub fn data_offset(&mut self) -> Result<u64> {
+        Ok(self.trailer.data_offset)
     }
 
     pub fn data(&mut self) -> Result<Vec<u8>> {
@@ -56,36 +280,552 @@ impl OnexFile {
         Ok(data_bytes)
     }
 
-    pub fn data_accessor(&mut self) -> Result<OffsetSeeker> {
-        self.f.seek(SeekFrom::Start(0))?;
+    /// Returns the payload, the zip archive holding the app.
+    pub fn data_accessor(&mut self) -> Result<OffsetSeeker<&mut R>> {
+        match self.section(SectionId::Payload)? {
+            Some(accessor) => Ok(accessor),
+            None => Err("This app does not have a payload section.".into()),
+        }
+    }
+
+    /// Like `data_accessor`, but hands over the underlying reader so the
+    /// payload can outlive this `OnexFile`.
+    pub fn into_data_accessor(mut self) -> Result<OffsetSeeker<R>> {
+        match self.sections.iter().find(|s| s.id == SectionId::Payload) {
+            Some(section) => {
+                self.f.seek(SeekFrom::Start(0))?;
+                Ok(OffsetSeeker::new(self.f, section.offset, section.length)?)
+            }
+            None => Err("This app does not have a payload section.".into()),
+        }
+    }
+
+    /// Returns the first section with the given ID, if there is one.
+    pub fn section(&mut self, id: SectionId) -> Result<Option<OffsetSeeker<&mut R>>> {
+        match self.sections.iter().find(|s| s.id == id).copied() {
+            Some(section) => Ok(Some(self.section_accessor(&section)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn section_bytes(&mut self, id: SectionId) -> Result<Option<Vec<u8>>> {
+        match self.section(id)? {
+            Some(mut accessor) => {
+                let mut bytes = Vec::new();
+                accessor.read_to_end(&mut bytes)?;
+                Ok(Some(bytes))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Reads every section into memory, in the order they appear in the
+    /// directory, so they can be laid out again after a different loader.
+    pub fn section_contents(&mut self) -> Result<Vec<(SectionId, Vec<u8>)>> {
+        self.sections
+            .clone()
+            .iter()
+            .map(|section| {
+                let mut bytes = Vec::new();
+                self.section_accessor(section)?.read_to_end(&mut bytes)?;
+                Ok((section.id, bytes))
+            })
+            .collect()
+    }
+
+    /// Returns how to run the app, from its manifest section or, for apps
+    /// packed before there was one, the `onex_run` file in its payload.
+    pub fn manifest(&mut self) -> Result<Manifest> {
+        if let Some(bytes) = self.section_bytes(SectionId::Manifest)? {
+            return match String::from_utf8(bytes) {
+                Ok(s) => Manifest::from_toml(&s),
+                Err(_) => Err("The manifest is not valid UTF-8.".into()),
+            };
+        }
+
+        let mut archive = ZipArchive::new(self.data_accessor()?)?;
+        let mut contents = String::new();
+        match archive.by_name(LEGACY_ENTRY_FILE) {
+            Ok(mut entry) => entry.read_to_string(&mut contents)?,
+            Err(ZipError::FileNotFound) => {
+                return Err(format!(
+                    "This app has neither a manifest nor an {} file.",
+                    LEGACY_ENTRY_FILE
+                )
+                .into())
+            }
+            Err(err) => return Err(err.into()),
+        };
+        Manifest::from_legacy_entry_file(&contents)
+    }
+
+    /// Checks every section, and the loader if it was recorded, against the
+    /// digests stored when the app was packed.
+    pub fn verify(&mut self) -> Result<()> {
+        if self.sections.iter().all(|s| s.digest.is_none()) {
+            return Err("This app does not contain a payload digest.".into());
+        }
+
+        for section in self.sections.clone() {
+            if let Some(expected) = section.digest {
+                let actual = sha256(&mut self.section_accessor(&section)?)?;
+                if actual != expected {
+                    return Err(format!(
+                        "The {} section digest does not match (expected {}, found {}).",
+                        section.id,
+                        to_hex(&expected),
+                        to_hex(&actual)
+                    )
+                    .into());
+                }
+            }
+        }
+
+        if let Some(expected) = self.trailer.loader_digest {
+            let actual = pe::image_digest(&mut self.f, self.trailer.data_offset)?;
+            if actual != expected {
+                return Err(format!(
+                    "The loader digest does not match (expected {}, found {}).",
+                    to_hex(&expected),
+                    to_hex(&actual)
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Signs every section of the app, returning the signature along with the
+    /// patch that embeds it in a signature section.
+    pub fn sign(&mut self, secret_key: &[u8]) -> Result<(PayloadSignature, TailPatch)> {
+        let mut sections = self.hashed_sections()?;
+        for (section, hashed) in self.sections.iter().zip(&sections) {
+            if section.digest.is_some() && section.digest != hashed.digest {
+                return Err(format!(
+                    "Refusing to sign an app whose {} section digest does not match.",
+                    section.id
+                )
+                .into());
+            }
+        }
+        let signature = signature::sign_digest(secret_key, &section::directory_digest(&sections))?;
+
+        let mut signature_bytes = signature.public_key.to_vec();
+        signature_bytes.extend(&signature.signature);
+
+        let data_offset = self.trailer.data_offset;
+        let offset = sections
+            .iter()
+            .map(|s| s.end())
+            .max()
+            .unwrap_or(data_offset);
+        sections.push(Section {
+            id: SectionId::Signature,
+            offset,
+            length: signature_bytes.len() as u64,
+            digest: Some(sha256_bytes(&signature_bytes)),
+        });
+        let directory = section::directory_bytes(&sections, data_offset);
+
+        let data_length = offset - data_offset + signature_bytes.len() as u64;
+        let trailer = Trailer::new(
+            data_offset,
+            data_length + directory.len() as u64,
+            self.trailer.loader_digest,
+        );
+
+        let mut bytes = signature_bytes;
+        bytes.extend(directory);
+        bytes.extend(trailer.to_bytes());
+        Ok((signature, TailPatch { offset, bytes }))
+    }
+
+    /// Checks that the app was signed by `public_key`, using either the given
+    /// detached signature or the one embedded in the app.
+    pub fn verify_signature(
+        &mut self,
+        public_key: &[u8],
+        detached_signature: Option<&[u8]>,
+    ) -> Result<()> {
+        let signature = match detached_signature {
+            Some(signature) => signature.to_vec(),
+            None => match self.section_bytes(SectionId::Signature)? {
+                Some(bytes) if bytes.len() == SIGNATURE_FIELD_LENGTH as usize => {
+                    let embedded = PayloadSignature {
+                        public_key: bytes[..PUBLIC_KEY_LENGTH].try_into().unwrap(),
+                        signature: bytes[PUBLIC_KEY_LENGTH..].try_into().unwrap(),
+                    };
+                    check_public_key(&embedded, public_key)?;
+                    embedded.signature.to_vec()
+                }
+                Some(_) => return Err("The signature section is corrupt.".into()),
+                None => return Err("This app is not signed.".into()),
+            },
+        };
+
+        let digest = section::directory_digest(&self.hashed_sections()?);
+        signature::verify_digest(public_key, &digest, &signature)
+    }
+
+    /// Returns every section except the signature, with digests computed
+    /// from what is currently on disk.
+    fn hashed_sections(&mut self) -> Result<Vec<Section>> {
+        self.sections
+            .clone()
+            .into_iter()
+            .filter(|s| s.id != SectionId::Signature)
+            .map(|mut section| {
+                section.digest = Some(sha256(&mut self.section_accessor(&section)?)?);
+                Ok(section)
+            })
+            .collect()
+    }
+
+    fn section_accessor(&mut self, section: &Section) -> Result<OffsetSeeker<&mut R>> {
         Ok(OffsetSeeker::new(
-            self.f.try_clone()?,
-            self.data_offset()?,
-            self.data_length()?,
+            &mut self.f,
+            section.offset,
+            section.length,
         )?)
     }
+}
 
-    pub fn validate(f: &mut File) -> Result<()> {
-        f.seek(SeekFrom::End(-(SIGNATURE.len() as i64)))?;
+/// Reads the section directory, or for files from before there was one,
+/// describes the whole data region as the payload.
+fn read_sections<R: Read + Seek>(r: &mut R, trailer: &Trailer) -> Result<Vec<Section>> {
+    if trailer.format_version == 0 {
+        return Ok(vec![Section {
+            id: SectionId::Payload,
+            offset: trailer.data_offset,
+            length: trailer.data_length,
+            digest: None,
+        }]);
+    }
 
-        let mut signature = String::new();
-        f.read_to_string(&mut signature)?;
+    if trailer.data_length < section::directory_length(0) {
+        return Err("The onex section directory is truncated.".into());
+    }
+    r.seek(SeekFrom::Start(
+        trailer.data_end() - section::directory_length(0),
+    ))?;
+    let mut count_bytes = [0; 4];
+    r.read_exact(&mut count_bytes)?;
 
-        if signature != SIGNATURE {
-            Err("Signature not found for executable file.".into())
-        } else {
-            Ok(())
+    let directory_length = section::parse_directory_length(&count_bytes);
+    if directory_length > trailer.data_length {
+        return Err("The onex section directory is truncated.".into());
+    }
+    r.seek(SeekFrom::Start(trailer.data_end() - directory_length))?;
+    let mut directory = vec![0; directory_length as usize];
+    r.read_exact(&mut directory)?;
+
+    section::parse_directory(
+        &directory,
+        trailer.data_offset,
+        trailer.data_length - directory_length,
+    )
+}
+
+fn check_public_key(embedded: &PayloadSignature, public_key: &[u8]) -> Result<()> {
+    if embedded.public_key[..] == *public_key {
+        Ok(())
+    } else {
+        Err("This app was signed with a different key.".into())
+    }
+}
+
+/// Finds where the trailer ends. That's normally the end of the file, but
+/// Authenticode signing a packed app appends a certificate table, padded to a
+/// multiple of 8 bytes, after the trailer and records where it is in the
+/// loader's PE headers.
+fn trailer_end<R: Read + Seek>(r: &mut R) -> Result<u64> {
+    let file_length = r.seek(SeekFrom::End(0))?;
+    let magic_before = |r: &mut R, end: u64| -> Result<bool> {
+        Ok(end >= MAGIC.len() as u64 && read_from_end(r, end, MAGIC.len() as u32)? == MAGIC)
+    };
+    if magic_before(r, file_length)? {
+        return Ok(file_length);
+    }
+
+    let table = match PeHeaders::read(r) {
+        Ok(PeHeaders {
+            certificate_table: Some(table),
+            ..
+        }) if table.offset < file_length && table.offset + table.length >= file_length => table,
+        _ => return Ok(file_length),
+    };
+    for padding in 0..8 {
+        if table.offset >= padding && magic_before(r, table.offset - padding)? {
+            return Ok(table.offset - padding);
         }
     }
+    Ok(file_length)
+}
+
+/// Reads the `length` bytes before `end`.
+fn read_from_end<R: Read + Seek>(r: &mut R, end: u64, length: u32) -> Result<Vec<u8>> {
+    r.seek(SeekFrom::Start(end - length as u64))?;
+    let mut bytes = vec![0; length as usize];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
 
-    fn file_length(&self) -> Result<u64> {
-        Ok(self.f.metadata()?.len())
+/// Reads a trailer field that starts `offset` bytes before `end`.
+fn read_field<R: Read + Seek>(
+    r: &mut R,
+    end: u64,
+    offset: u32,
+    length: usize,
+    trailer: &Trailer,
+) -> Result<Vec<u8>> {
+    if offset > trailer.header_length {
+        return Err("The onex trailer is corrupt.".into());
     }
+    r.seek(SeekFrom::Start(end - offset as u64))?;
+    let mut bytes = vec![0; length];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            pe::tests::{pe_bytes, set_certificate_table},
+            signature::generate_keypair,
+            SeekableVec,
+        },
+        std::io::Cursor,
+    };
+
+    fn read_trailer(bytes: Vec<u8>) -> Result<Trailer> {
+        Trailer::read(&mut SeekableVec::new(bytes))
+    }
+
+    fn generate(digest_loader: bool) -> Vec<u8> {
+        OnexFile::generate_bytes(b"loader".to_vec(), b"payload".to_vec(), digest_loader).unwrap()
+    }
+
+    fn with_onex_file<T, F: FnOnce(OnexFile<Cursor<&[u8]>>) -> Result<T>>(
+        bytes: &[u8],
+        f: F,
+    ) -> Result<T> {
+        OnexFile::new(Cursor::new(bytes)).and_then(f)
+    }
+
+    fn verify_bytes(bytes: &[u8]) -> Result<()> {
+        with_onex_file(bytes, |mut f| f.verify())
+    }
+
+    fn apply_patch(bytes: &[u8], patch: TailPatch) -> Vec<u8> {
+        let mut patched = bytes[..patch.offset as usize].to_vec();
+        patched.extend(patch.bytes);
+        patched
+    }
+
+    #[test]
+    fn trailer_round_trip() {
+        let bytes = generate(true);
+        let data_length = 7 + section::directory_length(1);
+        let header_length = TRAILER_LENGTH + DIGEST_LENGTH as u32;
+        assert_eq!(bytes.len() as u64, 6 + data_length + header_length as u64);
+
+        let trailer = read_trailer(bytes).unwrap();
+        assert_eq!(trailer.format_version, FORMAT_VERSION);
+        assert_eq!(trailer.header_length, header_length);
+        assert_eq!(trailer.flags, FLAG_LOADER_DIGEST);
+        assert_eq!(trailer.data_offset, 6);
+        assert_eq!(trailer.data_length, data_length);
+        assert_eq!(trailer.loader_digest, Some(sha256_bytes(b"loader")));
+    }
+
+    #[test]
+    fn trailer_reads_version_0() {
+        let mut bytes = b"loaderpayload".to_vec();
+        bytes.extend(&6_u64.to_le_bytes());
+        bytes.extend(MAGIC);
+
+        let trailer = read_trailer(bytes.clone()).unwrap();
+        assert_eq!(trailer.format_version, 0);
+        assert_eq!(trailer.header_length, LEGACY_TRAILER_LENGTH);
+        assert_eq!(trailer.data_offset, 6);
+        assert_eq!(trailer.data_length, 7);
+
+        let data = with_onex_file(&bytes, |mut f| f.data()).unwrap();
+        assert_eq!(data, b"payload");
+    }
+
+    #[test]
+    fn payload_outlives_onex_file() {
+        let onex_file = OnexFile::new(SeekableVec::new(generate(false))).unwrap();
+        let mut payload = String::new();
+        onex_file
+            .into_data_accessor()
+            .unwrap()
+            .read_to_string(&mut payload)
+            .unwrap();
+        assert_eq!(payload, "payload");
+    }
+
+    #[test]
+    fn trailer_rejects_other_versions() {
+        for &version in &[1, FORMAT_VERSION + 1] {
+            let mut bytes = generate(false);
+            let version_offset = bytes.len() - 8;
+            bytes[version_offset..version_offset + 4].copy_from_slice(&version.to_le_bytes());
+
+            let result = read_trailer(bytes);
+            assert_eq!(
+                format!("{}", result.err().unwrap()),
+                format!(
+                    "Unsupported onex format version {} (this build supports versions 0 and {}).",
+                    version, FORMAT_VERSION
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn trailer_requires_magic() {
+        assert!(read_trailer(b"not an onex app".to_vec()).is_err());
+        assert!(read_trailer(b"ONE".to_vec()).is_err());
+    }
+
+    #[test]
+    fn sections_are_readable() {
+        let bytes = OnexFile::generate_sections_bytes(
+            b"loader".to_vec(),
+            vec![
+                (SectionId::Payload, b"payload".to_vec()),
+                (SectionId::Manifest, b"manifest".to_vec()),
+                (SectionId::User(7), b"user".to_vec()),
+            ],
+            false,
+        )
+        .unwrap();
+
+        with_onex_file(&bytes, |mut f| {
+            let ids = f.sections().iter().map(|s| s.id).collect::<Vec<_>>();
+            assert_eq!(
+                ids,
+                vec![SectionId::Payload, SectionId::Manifest, SectionId::User(7)]
+            );
+
+            assert_eq!(f.data()?, b"payload");
+            assert_eq!(f.section_bytes(SectionId::Manifest)?.unwrap(), b"manifest");
+            assert_eq!(f.section_bytes(SectionId::User(7))?.unwrap(), b"user");
+            assert!(f.section(SectionId::Resources)?.is_none());
+            f.verify()
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn verify_detects_corruption() {
+        let bytes = generate(true);
+        assert!(verify_bytes(&bytes).is_ok());
+
+        let mut corrupt_payload = bytes.clone();
+        corrupt_payload[8] ^= 0xff;
+        let err = verify_bytes(&corrupt_payload).err().unwrap();
+        assert!(format!("{}", err).starts_with("The payload section digest does not match"));
+
+        let mut corrupt_loader = bytes;
+        corrupt_loader[0] ^= 0xff;
+        let err = verify_bytes(&corrupt_loader).err().unwrap();
+        assert!(format!("{}", err).starts_with("The loader digest does not match"));
+    }
+
+    #[test]
+    fn sign_embeds_signature() {
+        let (secret_key, public_key) = generate_keypair();
+        let bytes = generate(false);
+
+        let (signature, patch) = with_onex_file(&bytes, |mut f| f.sign(&secret_key)).unwrap();
+        let mut signed = apply_patch(&bytes, patch);
+
+        let sections = with_onex_file(&signed, |f| Ok(f.sections().to_vec())).unwrap();
+        assert_eq!(sections.last().unwrap().id, SectionId::Signature);
+        assert!(verify_bytes(&signed).is_ok());
+        assert!(with_onex_file(&signed, |mut f| f.verify_signature(&public_key, None)).is_ok());
+
+        let (_, other_public_key) = generate_keypair();
+        assert!(
+            with_onex_file(&signed, |mut f| f.verify_signature(&other_public_key, None)).is_err()
+        );
+        assert!(with_onex_file(&bytes, |mut f| f
+            .verify_signature(&public_key, Some(&signature.signature)))
+        .is_ok());
+
+        // Signing again replaces the old signature rather than adding another.
+        let (_, patch) = with_onex_file(&signed, |mut f| f.sign(&secret_key)).unwrap();
+        let resigned = apply_patch(&signed, patch);
+        assert_eq!(resigned.len(), signed.len());
+
+        signed[8] ^= 0xff;
+        assert!(with_onex_file(&signed, |mut f| f.verify_signature(&public_key, None)).is_err());
+    }
+
+    #[test]
+    fn trailer_found_before_authenticode_signature() {
+        let mut bytes =
+            OnexFile::generate_bytes(pe_bytes(0x8664, 0x22), b"payload".to_vec(), false).unwrap();
+        let expected = with_onex_file(&bytes, |f| Ok(*f.trailer())).unwrap();
+
+        // Signing pads the file to a multiple of 8 bytes, then appends the
+        // certificate table.
+        while bytes.len() % 8 != 0 {
+            bytes.push(0);
+        }
+        let table_offset = bytes.len() as u32;
+        bytes.extend(&[0x18, 0, 0, 0, 0, 2, 2, 0]);
+        bytes.extend(&[0xab; 16]);
+        set_certificate_table(&mut bytes, table_offset, 0x18);
+
+        let trailer = with_onex_file(&bytes, |f| Ok(*f.trailer())).unwrap();
+        assert_eq!(trailer, expected);
+        assert_eq!(
+            with_onex_file(&bytes, |mut f| f.data()).unwrap(),
+            b"payload"
+        );
+        assert!(verify_bytes(&bytes).is_ok());
+
+        // Without the directory entry, there's nothing to find the trailer by.
+        set_certificate_table(&mut bytes, 0, 0);
+        assert!(read_trailer(bytes).is_err());
+    }
+
+    #[test]
+    fn loader_digest_survives_authenticode_signing() {
+        let mut bytes =
+            OnexFile::generate_bytes(pe_bytes(0x8664, 0x22), b"payload".to_vec(), true).unwrap();
+        assert!(verify_bytes(&bytes).is_ok());
+
+        // Signing fills in the checksum and the certificate table's entry,
+        // neither of which the loader digest covers.
+        while bytes.len() % 8 != 0 {
+            bytes.push(0);
+        }
+        let table_offset = bytes.len() as u32;
+        bytes.extend(&[0x18, 0, 0, 0, 0, 2, 2, 0]);
+        bytes.extend(&[0xab; 16]);
+        set_certificate_table(&mut bytes, table_offset, 0x18);
+        bytes[0x58 + 64..0x58 + 68].copy_from_slice(&0x1234u32.to_le_bytes());
+        assert!(verify_bytes(&bytes).is_ok());
 
-    fn data_length(&mut self) -> Result<u64> {
-        Ok(self.file_length()?
-            - self.data_offset()?
-            - SIGNATURE.len() as u64
-            - DATA_OFFSET_LENGTH as u64)
+        // The rest of the headers are still covered.
+        bytes[0x58 + 68] ^= 0xff;
+        let err = verify_bytes(&bytes).err().unwrap();
+        assert!(format!("{}", err).starts_with("The loader digest does not match"));
     }
 }
@@ -0,0 +1,365 @@
+use {
+    crate::{cache::force_remove_dir_all, result::Result},
+    fs2::FileExt,
+    log::{error, trace},
+    std::{
+        fs::{self, DirEntry, File, OpenOptions},
+        io::{self, Read, Write},
+        path::{Path, PathBuf},
+        process,
+        time::{Duration, SystemTime},
+    },
+};
+
+/// What every instance directory's name starts with, followed by a UUID.
+pub const INSTANCE_PREFIX: &str = "onex_";
+/// Added to an instance directory's name to name its lock file.
+const LOCK_EXTENSION: &str = "lock";
+/// How long to leave instance directories without a lock file, which loaders
+/// from before there were lock files may still be running from.
+const UNLOCKED_GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long to leave instances whose lock file doesn't hold a process ID yet,
+/// which a loader may be about to lock and write it to.
+const STARTING_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// Where loaders create the directories apps run from.
+#[cfg(windows)]
+pub fn instances_dir() -> Result<PathBuf> {
+    crate::windows::get_temp_dir()
+}
+
+/// Where loaders create the directories apps run from.
+#[cfg(not(windows))]
+pub fn instances_dir() -> Result<PathBuf> {
+    Ok(std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|path| path.is_dir())
+        .unwrap_or_else(std::env::temp_dir))
+}
+
+/// One run of an app, which holds the lock on its directory's lock file so
+/// the directory can be cleaned up if the loader dies without removing it.
+pub struct Instance {
+    dir: PathBuf,
+    lock_path: PathBuf,
+    _lock: File,
+}
+
+impl Instance {
+    /// Claims the instance directory `name` in `parent`, before anything
+    /// creates it, recording this process's ID in its lock file.
+    pub fn claim(parent: &Path, name: &str) -> Result<Self> {
+        let dir = parent.join(name);
+        let lock_path = lock_path(&dir);
+        let mut lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)?;
+        lock.lock_exclusive()?;
+        write!(lock, "{}", process::id())?;
+
+        Ok(Instance {
+            dir,
+            lock_path,
+            _lock: lock,
+        })
+    }
+
+    /// The directory the app runs from, which the caller creates and removes.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.lock_path) {
+            error!(
+                "Failed to remove lock file {}: {}",
+                self.lock_path.display(),
+                err
+            );
+        }
+    }
+}
+
+/// An instance directory left behind by a loader that's no longer running.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrphanedInstance {
+    /// The instance directory, which may already be gone if only its lock
+    /// file was left behind.
+    pub dir: PathBuf,
+    /// The process the loader ran as, if it was recorded.
+    pub pid: Option<u32>,
+}
+
+/// Finds the instance directories in `parent` whose loaders have exited, or
+/// were killed, without removing them. Entries that can't be checked, such as
+/// another user's in a shared temporary directory, are logged and skipped.
+pub fn orphaned_instances(parent: &Path) -> Result<Vec<OrphanedInstance>> {
+    let mut orphans = Vec::new();
+    for entry in fs::read_dir(parent)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                error!("Failed to read {}: {}", parent.display(), err);
+                continue;
+            }
+        };
+        match orphaned_instance(parent, &entry) {
+            Ok(Some(orphan)) => orphans.push(orphan),
+            Ok(None) => {}
+            Err(err) => error!(
+                "Failed to check whether {} is orphaned: {}",
+                entry.path().display(),
+                err
+            ),
+        }
+    }
+
+    orphans.sort_by(|a, b| a.dir.cmp(&b.dir));
+    Ok(orphans)
+}
+
+/// The orphaned instance `entry` of `parent` belongs to, if it's an instance
+/// directory or lock file and its loader has gone.
+fn orphaned_instance(parent: &Path, entry: &DirEntry) -> io::Result<Option<OrphanedInstance>> {
+    let name = entry.file_name().to_string_lossy().into_owned();
+    let path = entry.path();
+
+    let lock_file_stem = path
+        .extension()
+        .filter(|extension| *extension == LOCK_EXTENSION)
+        .and(path.file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned());
+    let dir = match lock_file_stem {
+        // Lock files are dealt with along with their directory, unless that's
+        // already gone.
+        Some(stem) if is_instance_name(&stem) && !parent.join(&stem).exists() => parent.join(&stem),
+        Some(_) => return Ok(None),
+        None if is_instance_name(&name) && entry.file_type()?.is_dir() => path,
+        None => return Ok(None),
+    };
+    let lock_path = lock_path(&dir);
+
+    Ok(match owner(&lock_path)? {
+        Owner::Running => None,
+        Owner::Gone(pid) => Some(OrphanedInstance {
+            dir,
+            pid: Some(pid),
+        }),
+        Owner::Starting if age(&lock_path)? > STARTING_GRACE_PERIOD => {
+            Some(OrphanedInstance { dir, pid: None })
+        }
+        Owner::Unknown if age(&dir)? > UNLOCKED_GRACE_PERIOD => {
+            Some(OrphanedInstance { dir, pid: None })
+        }
+        Owner::Starting | Owner::Unknown => None,
+    })
+}
+
+/// How long ago `path` was last modified.
+fn age(path: &Path) -> io::Result<Duration> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default())
+}
+
+/// Removes an orphaned instance directory and its lock file. Returns `false`
+/// without removing anything if a loader has claimed it since it was found.
+pub fn remove_orphaned_instance(instance: &OrphanedInstance) -> Result<bool> {
+    let lock_path = lock_path(&instance.dir);
+    let lock = match OpenOptions::new().read(true).write(true).open(&lock_path) {
+        Ok(lock) => Some(lock),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    if let Some(lock) = &lock {
+        match lock.try_lock_exclusive() {
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => return Ok(false),
+            result => result?,
+        }
+    }
+
+    if instance.dir.exists() {
+        force_remove_dir_all(&instance.dir)?;
+    }
+    // The lock file goes last, so an interrupted removal is found again.
+    if lock.is_some() {
+        drop(lock);
+        match fs::remove_file(&lock_path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+    }
+    Ok(true)
+}
+
+/// Removes every orphaned instance directory in `parent`, logging rather than
+/// failing on any that can't be removed, and returns those that were.
+pub fn sweep_orphaned_instances(parent: &Path) -> Result<Vec<OrphanedInstance>> {
+    let mut removed = Vec::new();
+    for instance in orphaned_instances(parent)? {
+        trace!("Removing orphaned instance {}", instance.dir.display());
+        match remove_orphaned_instance(&instance) {
+            Ok(true) => removed.push(instance),
+            Ok(false) => {}
+            Err(err) => error!(
+                "Failed to remove orphaned instance {}: {}",
+                instance.dir.display(),
+                err
+            ),
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether `name` is `onex_` followed by a hyphenated UUID, so nothing else
+/// that happens to start with `onex_` is touched.
+fn is_instance_name(name: &str) -> bool {
+    match name.strip_prefix(INSTANCE_PREFIX) {
+        Some(uuid) => {
+            uuid.len() == 36
+                && uuid.char_indices().all(|(i, c)| match i {
+                    8 | 13 | 18 | 23 => c == '-',
+                    _ => c.is_ascii_hexdigit(),
+                })
+        }
+        None => false,
+    }
+}
+
+fn lock_path(dir: &Path) -> PathBuf {
+    dir.with_extension(LOCK_EXTENSION)
+}
+
+enum Owner {
+    Running,
+    /// The loader has gone, and this was its process ID.
+    Gone(u32),
+    /// The lock file doesn't hold a process ID, either because its loader is
+    /// between creating and locking it, or because it died in between.
+    Starting,
+    /// There's no lock file to tell.
+    Unknown,
+}
+
+fn owner(lock_path: &Path) -> io::Result<Owner> {
+    let mut lock = match OpenOptions::new().read(true).write(true).open(lock_path) {
+        Ok(lock) => lock,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Owner::Unknown),
+        Err(err) => return Err(err),
+    };
+    match lock.try_lock_exclusive() {
+        Err(err) if err.kind() == fs2::lock_contended_error().kind() => Ok(Owner::Running),
+        Err(err) => Err(err),
+        Ok(()) => {
+            let mut pid = String::new();
+            lock.read_to_string(&mut pid)?;
+            lock.unlock()?;
+            Ok(match pid.trim().parse() {
+                Ok(pid) => Owner::Gone(pid),
+                Err(_) => Owner::Starting,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, filetime::FileTime, std::env};
+
+    const LIVE: &str = "onex_00000000-0000-0000-0000-000000000001";
+    const DEAD: &str = "onex_00000000-0000-0000-0000-000000000002";
+    const UNLOCKED_NEW: &str = "onex_00000000-0000-0000-0000-000000000003";
+    const UNLOCKED_OLD: &str = "onex_00000000-0000-0000-0000-000000000004";
+    const STRAY_LOCK: &str = "onex_00000000-0000-0000-0000-000000000005";
+    const STARTING: &str = "onex_00000000-0000-0000-0000-000000000006";
+
+    #[test]
+    fn recognizes_instance_names() {
+        assert!(is_instance_name(LIVE));
+        assert!(is_instance_name(
+            "onex_c60eba2d-f442-4f8e-894b-8256c748b594"
+        ));
+        assert!(!is_instance_name(
+            "onex_c60eba2d-f442-4f8e-894b-8256c748b59"
+        ));
+        assert!(!is_instance_name(
+            "onex_c60eba2df442-4f8e-894b-8256c748b5945"
+        ));
+        assert!(!is_instance_name("onex_zip_app_dir_checks_entry"));
+        assert!(!is_instance_name("c60eba2d-f442-4f8e-894b-8256c748b594"));
+    }
+
+    #[test]
+    fn sweeps_only_orphaned_instances() {
+        let parent = env::temp_dir().join("onex_sweeps_only_orphaned_instances");
+        if parent.exists() {
+            fs::remove_dir_all(&parent).unwrap();
+        }
+        fs::create_dir_all(&parent).unwrap();
+
+        let live = Instance::claim(&parent, LIVE).unwrap();
+        fs::create_dir(live.dir()).unwrap();
+        // A loader that died leaves its lock file behind, but not its lock.
+        fs::create_dir(parent.join(DEAD)).unwrap();
+        fs::write(parent.join(DEAD).join("app.exe"), b"app").unwrap();
+        for name in &[DEAD, STRAY_LOCK] {
+            fs::write(lock_path(&parent.join(name)), process::id().to_string()).unwrap();
+        }
+        fs::create_dir(parent.join(UNLOCKED_NEW)).unwrap();
+        fs::create_dir(parent.join(UNLOCKED_OLD)).unwrap();
+        let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+        filetime::set_file_mtime(
+            parent.join(UNLOCKED_OLD),
+            FileTime::from_system_time(two_days_ago),
+        )
+        .unwrap();
+        fs::create_dir(parent.join("onex_something_else")).unwrap();
+        // A loader that's yet to lock its lock file and write its ID to it.
+        fs::write(lock_path(&parent.join(STARTING)), b"").unwrap();
+
+        let orphans = orphaned_instances(&parent).unwrap();
+        assert_eq!(
+            orphans,
+            vec![
+                OrphanedInstance {
+                    dir: parent.join(DEAD),
+                    pid: Some(process::id()),
+                },
+                OrphanedInstance {
+                    dir: parent.join(UNLOCKED_OLD),
+                    pid: None,
+                },
+                OrphanedInstance {
+                    dir: parent.join(STRAY_LOCK),
+                    pid: Some(process::id()),
+                },
+            ]
+        );
+
+        assert_eq!(sweep_orphaned_instances(&parent).unwrap(), orphans);
+        let mut left = fs::read_dir(&parent)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(
+            left,
+            vec![
+                LIVE.to_owned(),
+                format!("{}.lock", LIVE),
+                UNLOCKED_NEW.to_owned(),
+                format!("{}.lock", STARTING),
+                "onex_something_else".to_owned(),
+            ]
+        );
+
+        drop(live);
+        assert!(!lock_path(&parent.join(LIVE)).exists());
+        fs::remove_dir_all(&parent).unwrap();
+    }
+}
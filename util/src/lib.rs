@@ -1,15 +1,51 @@
+mod cache;
+mod command_line;
+mod digest;
+mod elf;
 mod file;
+mod instance;
+mod manifest;
 mod misc;
+mod pe;
+#[cfg(windows)]
 mod projfs;
 mod result;
+mod section;
+mod signature;
+#[cfg(windows)]
 mod windows;
+mod writer;
 mod zip;
 
 pub use crate::{
-    file::OnexFile,
-    misc::{OffsetSeeker, ReadSeek, SeekableVec},
+    cache::{
+        force_remove_dir_all, AppCache, CacheEntry, CacheRecord, CachedApp, CachedFile,
+        CACHE_DIR_VAR,
+    },
+    command_line::{build_command_line, parse_command_line, quote_arg},
+    digest::{from_hex, sha256, to_hex, Digest},
+    elf::ElfHeader,
+    file::{OnexFile, TailPatch, Trailer, FORMAT_VERSION},
+    instance::{
+        instances_dir, orphaned_instances, remove_orphaned_instance, sweep_orphaned_instances,
+        Instance, OrphanedInstance, INSTANCE_PREFIX,
+    },
+    manifest::{Manifest, WorkingDir, APP_DIR_VAR, EXE_PATH_VAR, LEGACY_ENTRY_FILE, VERSION_VAR},
+    misc::{OffsetSeeker, OffsetWriter, ReadSeek, SeekableVec},
+    pe::{CertificateTable, Machine, PeHeaders},
+    result::{Error, Result, UnsafePathError},
+    section::{Section, SectionId},
+    signature::{generate_keypair, PayloadSignature, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH},
+    writer::OnexWriter,
+    zip::{
+        app_dir_entries, compression_method_name, extract_entries, extract_zip, format_timestamp,
+        list_entries, open_entry, parse_compression_method, write_app_dir, zip_app_dir,
+        AppDirEntry, AppDirEntryKind, ArchiveEntry, ArchiveEntryKind, ArchiveOptions, Compression,
+        ExistingFilePolicy, ExtractOptions, UnsafePathPolicy, IGNORE_FILE,
+    },
+};
+#[cfg(windows)]
+pub use crate::{
     projfs::ProjfsProvider,
-    result::{Error, Result},
     windows::{get_temp_dir, raw_str_to_os_string, to_u16_vec},
-    zip::{extract_zip, list_zip_contents, zip_app_dir},
 };
@@ -0,0 +1,193 @@
+use {
+    crate::result::Result,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        path::{Component, Path},
+    },
+};
+
+/// The name of the file older apps used to name their entry point.
+pub const LEGACY_ENTRY_FILE: &str = "onex_run";
+
+/// Set for the entry to the directory the app was unpacked to.
+pub const APP_DIR_VAR: &str = "ONEX_APP_DIR";
+/// Set for the entry to the path of the packed app that was started.
+pub const EXE_PATH_VAR: &str = "ONEX_EXE_PATH";
+/// Set for the entry to the version of the loader that started it.
+pub const VERSION_VAR: &str = "ONEX_VERSION";
+
+/// Where the entry executable is started from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkingDir {
+    /// Wherever the packed app was started from.
+    Caller,
+    /// The root of the unpacked app.
+    AppRoot,
+}
+
+impl Default for WorkingDir {
+    fn default() -> Self {
+        WorkingDir::Caller
+    }
+}
+
+/// Describes how to run a packed app, stored as TOML in its manifest section.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    /// The executable to run, relative to the root of the app.
+    pub entry: String,
+    /// Arguments passed to the entry before any given on the command line.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub working_dir: WorkingDir,
+    /// Run the app from the user's extraction cache, rather than unpacking it
+    /// afresh on every launch.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub cache: bool,
+    // Tables have to come after plain values when serializing to TOML.
+    /// Variables set for the entry on top of the caller's environment.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn new(entry: String) -> Self {
+        Manifest {
+            entry,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self> {
+        let mut manifest: Manifest = toml::from_str(s)?;
+        manifest.normalize_entry();
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Builds a manifest from the contents of an `onex_run` file.
+    pub fn from_legacy_entry_file(contents: &str) -> Result<Self> {
+        let mut manifest = Manifest::new(contents.trim().to_owned());
+        manifest.normalize_entry();
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Drops any `.` components from a relative entry, and joins the rest with
+    /// `/`, the way the app's files are named in its archive.
+    pub fn normalize_entry(&mut self) {
+        let entry = Path::new(&self.entry);
+        if entry
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            self.entry = entry
+                .components()
+                .filter_map(|c| match c {
+                    Component::Normal(name) => Some(name.to_string_lossy()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let entry = Path::new(&self.entry);
+        if self.entry.is_empty()
+            || !entry
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(format!(
+                "The entry '{}' must be a relative path inside the app.",
+                self.entry
+            )
+            .into());
+        }
+
+        for (key, value) in &self.env {
+            if key.is_empty() || key.contains(&['=', '\0'][..]) || value.contains('\0') {
+                return Err(format!("The environment variable '{}' can't be set.", key).into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_round_trip() {
+        let mut manifest = Manifest::new("bin/app.exe".to_owned());
+        manifest.name = Some("app".to_owned());
+        manifest.args = vec!["--flag".to_owned()];
+        manifest.env.insert("KEY".to_owned(), "value".to_owned());
+        manifest.working_dir = WorkingDir::AppRoot;
+        manifest.cache = true;
+
+        let toml = manifest.to_toml().unwrap();
+        assert_eq!(Manifest::from_toml(&toml).unwrap(), manifest);
+    }
+
+    #[test]
+    fn manifest_defaults() {
+        let manifest = Manifest::from_toml("entry = 'app.exe'").unwrap();
+        assert_eq!(manifest, Manifest::new("app.exe".to_owned()));
+        assert_eq!(manifest.working_dir, WorkingDir::Caller);
+    }
+
+    #[test]
+    fn manifest_rejects_bad_entries() {
+        assert!(Manifest::from_toml("entry = ''").is_err());
+        assert!(Manifest::from_toml("entry = './'").is_err());
+        assert!(Manifest::from_toml("entry = '../app.exe'").is_err());
+        assert!(Manifest::from_toml("entry = '/app.exe'").is_err());
+        assert!(Manifest::from_toml("args = []").is_err());
+        assert!(Manifest::from_toml("entry = 'app.exe'\n[env]\n'A=B' = 'c'").is_err());
+        assert!(Manifest::from_toml("entry = 'app.exe'\n[env]\n'' = 'c'").is_err());
+    }
+
+    #[test]
+    fn manifest_normalizes_entry() {
+        assert_eq!(
+            Manifest::from_toml("entry = './bin/./app.exe'")
+                .unwrap()
+                .entry,
+            "bin/app.exe"
+        );
+        assert_eq!(
+            Manifest::from_legacy_entry_file("./app.exe\n")
+                .unwrap()
+                .entry,
+            "app.exe"
+        );
+    }
+
+    #[test]
+    fn manifest_from_legacy_entry_file() {
+        assert_eq!(
+            Manifest::from_legacy_entry_file("testapp.exe\r\n").unwrap(),
+            Manifest::new("testapp.exe".to_owned())
+        );
+    }
+}
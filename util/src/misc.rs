@@ -1,20 +1,19 @@
-use std::{
-    fs::File,
-    io::{self, Read, Seek, SeekFrom, Write},
-};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 
 pub trait ReadSeek: Read + Seek + Send + Sync {}
 impl<T> ReadSeek for T where T: Read + Seek + Send + Sync {}
 
-pub struct OffsetSeeker {
-    file: File,
+/// Reads the `length` bytes of `file` starting at `offset` as though they were
+/// a file of their own.
+pub struct OffsetSeeker<R: Read + Seek> {
+    file: R,
     offset: u64,
     cursor: u64,
     length: u64,
 }
 
-impl OffsetSeeker {
-    pub fn new(mut file: File, offset: u64, length: u64) -> io::Result<Self> {
+impl<R: Read + Seek> OffsetSeeker<R> {
+    pub fn new(mut file: R, offset: u64, length: u64) -> io::Result<Self> {
         file.seek(SeekFrom::Start(offset))?;
         Ok(OffsetSeeker {
             file,
@@ -25,7 +24,7 @@ impl OffsetSeeker {
     }
 }
 
-impl Seek for OffsetSeeker {
+impl<R: Read + Seek> Seek for OffsetSeeker<R> {
     fn seek(&mut self, seek_from: SeekFrom) -> io::Result<u64> {
         let new_cursor = match seek_from {
             SeekFrom::Start(i) => i as i64,
@@ -51,7 +50,7 @@ impl Seek for OffsetSeeker {
     }
 }
 
-impl Read for OffsetSeeker {
+impl<R: Read + Seek> Read for OffsetSeeker<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let resized_buf = if self.cursor + buf.len() as u64 > self.length {
             &mut buf[..(self.length - self.cursor) as usize]
@@ -64,6 +63,60 @@ impl Read for OffsetSeeker {
     }
 }
 
+/// Writes to `inner` as though it started at `offset`, so something that
+/// records its own positions (like a zip archive) can be written partway
+/// through a file. Anything already before `offset` is out of reach.
+pub struct OffsetWriter<W: Write + Seek> {
+    inner: W,
+    offset: u64,
+}
+
+impl<W: Write + Seek> OffsetWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        let offset = inner.seek(SeekFrom::End(0))?;
+        Ok(OffsetWriter { inner, offset })
+    }
+}
+
+impl<W: Write + Seek> Seek for OffsetWriter<W> {
+    fn seek(&mut self, seek_from: SeekFrom) -> io::Result<u64> {
+        let position = match seek_from {
+            SeekFrom::Start(i) => self.inner.seek(SeekFrom::Start(self.offset + i))?,
+            SeekFrom::End(i) => {
+                let end = self.inner.seek(SeekFrom::End(0))?;
+                if (end - self.offset) as i64 + i < 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "Cannot seek before byte 0.",
+                    ));
+                }
+                self.inner.seek(SeekFrom::End(i))?
+            }
+            SeekFrom::Current(i) => {
+                let current = self.inner.stream_position()?;
+                if (current - self.offset) as i64 + i < 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "Cannot seek before byte 0.",
+                    ));
+                }
+                self.inner.seek(SeekFrom::Current(i))?
+            }
+        };
+        Ok(position - self.offset)
+    }
+}
+
+impl<W: Write + Seek> Write for OffsetWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 pub struct SeekableVec {
     cursor: usize,
     vec: Vec<u8>,
@@ -195,6 +248,37 @@ mod tests {
         }
     }
 
+    #[test]
+    fn offset_seeker_reads_its_range() {
+        let mut s = SeekableVec::new(b"loaderpayloadtrailer".to_vec());
+        let mut seeker = OffsetSeeker::new(&mut s, 6, 7).unwrap();
+
+        let mut contents = String::new();
+        seeker.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "payload");
+
+        assert_eq!(seeker.seek(SeekFrom::End(-4)).unwrap(), 3);
+        contents.clear();
+        seeker.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "load");
+        assert!(seeker.seek(SeekFrom::Current(-8)).is_err());
+    }
+
+    #[test]
+    fn offset_writer_hides_earlier_bytes() {
+        let mut s = SeekableVec::new(b"loader".to_vec());
+        {
+            let mut w = OffsetWriter::new(&mut s).unwrap();
+            w.write_all(b"abc").unwrap();
+            assert_eq!(w.stream_position().unwrap(), 3);
+            assert_eq!(w.seek(SeekFrom::Start(1)).unwrap(), 1);
+            w.write_all(b"B").unwrap();
+            assert_eq!(w.seek(SeekFrom::End(0)).unwrap(), 3);
+            assert!(w.seek(SeekFrom::End(-4)).is_err());
+        }
+        assert_eq!(s.into_vec(), b"loaderaBc".to_vec());
+    }
+
     #[test]
     fn seekable_vec_random_reads_writes() {
         let mut s = SeekableVec::new(Vec::new());
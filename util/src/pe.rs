@@ -0,0 +1,326 @@
+use {
+    crate::{
+        digest::{Digest, DIGEST_LENGTH},
+        result::{Error, Result},
+    },
+    sha2::{Digest as _, Sha256},
+    std::{
+        convert::TryInto,
+        fmt,
+        io::{self, Read, Seek, SeekFrom},
+        iter,
+    },
+};
+
+const DOS_MAGIC: &[u8; 2] = b"MZ";
+const PE_SIGNATURE: &[u8; 4] = b"PE\0\0";
+const DOS_HEADER_LENGTH: usize = 64;
+/// Where the DOS header keeps the offset of the PE signature.
+const PE_OFFSET_FIELD: usize = 0x3c;
+const COFF_HEADER_LENGTH: usize = 20;
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+/// Where the optional header keeps the image's checksum, in both formats.
+const CHECKSUM_FIELD: usize = 64;
+/// The index of the data directory describing the certificate table, which
+/// holds the image's Authenticode signatures.
+const SECURITY_DIRECTORY: usize = 4;
+
+/// The image can be run, rather than being an object file to link.
+const IMAGE_FILE_EXECUTABLE_IMAGE: u16 = 0x0002;
+/// The image is a DLL, which can be loaded but not run on its own.
+const IMAGE_FILE_DLL: u16 = 0x2000;
+
+/// The CPU a PE image is built for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Machine {
+    I386,
+    Amd64,
+    Arm64,
+    Unknown(u16),
+}
+
+impl Machine {
+    pub fn from_raw(raw: u16) -> Self {
+        match raw {
+            0x014c => Machine::I386,
+            0x8664 => Machine::Amd64,
+            0xaa64 => Machine::Arm64,
+            r => Machine::Unknown(r),
+        }
+    }
+
+    pub fn to_raw(self) -> u16 {
+        match self {
+            Machine::I386 => 0x014c,
+            Machine::Amd64 => 0x8664,
+            Machine::Arm64 => 0xaa64,
+            Machine::Unknown(r) => r,
+        }
+    }
+
+    /// The name Rust gives this architecture, as in `std::env::consts::ARCH`.
+    pub fn arch(self) -> Option<&'static str> {
+        match self {
+            Machine::I386 => Some("x86"),
+            Machine::Amd64 => Some("x86_64"),
+            Machine::Arm64 => Some("aarch64"),
+            Machine::Unknown(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.arch() {
+            Some(arch) => arch.fmt(f),
+            None => write!(f, "unknown machine {:#06x}", self.to_raw()),
+        }
+    }
+}
+
+/// Where the Authenticode certificate table sits in a PE file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CertificateTable {
+    /// The offset of the table from the start of the file.
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// The parts of a PE image's headers onex cares about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeHeaders {
+    pub machine: Machine,
+    /// The COFF header's `IMAGE_FILE_*` flags.
+    pub characteristics: u16,
+    /// The image's signatures, if it's been signed.
+    pub certificate_table: Option<CertificateTable>,
+}
+
+impl PeHeaders {
+    /// Whether the image is a program that can be started, rather than a DLL
+    /// or an object file.
+    pub fn is_executable(&self) -> bool {
+        self.characteristics & IMAGE_FILE_EXECUTABLE_IMAGE != 0
+            && self.characteristics & IMAGE_FILE_DLL == 0
+    }
+
+    /// Reads the headers of the PE image at the start of `r`.
+    pub fn read<R: Read + Seek>(r: &mut R) -> Result<Self> {
+        match read_raw_headers(r)? {
+            Some(raw) => Ok(PeHeaders {
+                machine: Machine::from_raw(u16::from_le_bytes([raw.coff[4], raw.coff[5]])),
+                characteristics: u16::from_le_bytes([raw.coff[22], raw.coff[23]]),
+                certificate_table: certificate_table(&raw.optional_header),
+            }),
+            None => Err(not_pe()),
+        }
+    }
+}
+
+/// Hashes the first `length` bytes of `r` leaving out the fields Authenticode
+/// signing rewrites, as Authenticode itself does: a PE image's checksum and
+/// the data directory entry for its certificate table. Anything that isn't a
+/// PE image is hashed whole.
+pub(crate) fn image_digest<R: Read + Seek>(r: &mut R, length: u64) -> Result<Digest> {
+    let mut skipped = Vec::new();
+    if let Some(raw) = read_raw_headers(r)? {
+        if raw.optional_header.len() >= CHECKSUM_FIELD + 4 {
+            skipped.push((raw.optional_header_offset + CHECKSUM_FIELD as u64, 4));
+        }
+        if let Some(entry_offset) = security_entry_offset(&raw.optional_header) {
+            skipped.push((raw.optional_header_offset + entry_offset as u64, 8));
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    let mut position = 0;
+    r.seek(SeekFrom::Start(0))?;
+    for (offset, skipped_length) in skipped.into_iter().chain(iter::once((length, 0))) {
+        let offset = offset.min(length);
+        io::copy(
+            &mut (&mut *r).take(offset.saturating_sub(position)),
+            &mut hasher,
+        )?;
+        position = (offset + skipped_length).min(length);
+        r.seek(SeekFrom::Start(position))?;
+    }
+
+    let mut digest = [0; DIGEST_LENGTH];
+    digest.copy_from_slice(&hasher.finalize());
+    Ok(digest)
+}
+
+/// A PE image's headers as they're laid out in the file.
+struct RawHeaders {
+    /// The PE signature followed by the COFF header.
+    coff: [u8; 4 + COFF_HEADER_LENGTH],
+    /// Where the optional header starts in the file.
+    optional_header_offset: u64,
+    optional_header: Vec<u8>,
+}
+
+/// Reads the headers of the PE image at the start of `r`, or `None` if it
+/// isn't one.
+fn read_raw_headers<R: Read + Seek>(r: &mut R) -> io::Result<Option<RawHeaders>> {
+    match try_read_raw_headers(r) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        result => result,
+    }
+}
+
+fn try_read_raw_headers<R: Read + Seek>(r: &mut R) -> io::Result<Option<RawHeaders>> {
+    let mut dos_header = [0; DOS_HEADER_LENGTH];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut dos_header)?;
+    if &dos_header[..2] != DOS_MAGIC {
+        return Ok(None);
+    }
+
+    let pe_offset = u32::from_le_bytes(
+        dos_header[PE_OFFSET_FIELD..PE_OFFSET_FIELD + 4]
+            .try_into()
+            .unwrap(),
+    );
+    let mut coff = [0; 4 + COFF_HEADER_LENGTH];
+    r.seek(SeekFrom::Start(pe_offset as u64))?;
+    r.read_exact(&mut coff)?;
+    if &coff[..4] != PE_SIGNATURE {
+        return Ok(None);
+    }
+
+    let optional_header_length = u16::from_le_bytes([coff[20], coff[21]]) as usize;
+    let mut optional_header = vec![0; optional_header_length];
+    r.read_exact(&mut optional_header)?;
+
+    Ok(Some(RawHeaders {
+        coff,
+        optional_header_offset: pe_offset as u64 + coff.len() as u64,
+        optional_header,
+    }))
+}
+
+/// Finds the certificate table in the optional header's data directories.
+fn certificate_table(optional_header: &[u8]) -> Option<CertificateTable> {
+    let u32_at = |offset: usize| {
+        optional_header
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let entry_offset = security_entry_offset(optional_header)?;
+    match (u32_at(entry_offset)?, u32_at(entry_offset + 4)?) {
+        (_, 0) | (0, _) => None,
+        (offset, length) => Some(CertificateTable {
+            offset: offset as u64,
+            length: length as u64,
+        }),
+    }
+}
+
+/// Where the certificate table's data directory entry is in the optional
+/// header, if it has one.
+fn security_entry_offset(optional_header: &[u8]) -> Option<usize> {
+    let magic = u16::from_le_bytes(optional_header.get(..2)?.try_into().unwrap());
+    // The number of data directories comes right before the directories.
+    let directories_offset = match magic {
+        PE32_MAGIC => 96,
+        PE32_PLUS_MAGIC => 112,
+        _ => return None,
+    };
+    let directory_count = optional_header.get(directories_offset - 4..directories_offset)?;
+    if u32::from_le_bytes(directory_count.try_into().unwrap()) as usize <= SECURITY_DIRECTORY {
+        return None;
+    }
+
+    let entry_offset = directories_offset + SECURITY_DIRECTORY * 8;
+    if entry_offset + 8 > optional_header.len() {
+        return None;
+    }
+    Some(entry_offset)
+}
+
+fn not_pe() -> Error {
+    "This is not a PE image.".into()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, std::io::Cursor};
+
+    /// Where `pe_bytes` puts the certificate table's data directory entry.
+    const SECURITY_ENTRY: usize = 0x58 + 112 + SECURITY_DIRECTORY * 8;
+
+    /// Builds the headers of a PE32+ image for `machine`.
+    pub(crate) fn pe_bytes(machine: u16, characteristics: u16) -> Vec<u8> {
+        let mut bytes = vec![0; 0x200];
+        bytes[..2].copy_from_slice(DOS_MAGIC);
+        bytes[PE_OFFSET_FIELD..PE_OFFSET_FIELD + 4].copy_from_slice(&0x40u32.to_le_bytes());
+        bytes[0x40..0x44].copy_from_slice(PE_SIGNATURE);
+        bytes[0x44..0x46].copy_from_slice(&machine.to_le_bytes());
+        bytes[0x54..0x56].copy_from_slice(&0xf0u16.to_le_bytes());
+        bytes[0x56..0x58].copy_from_slice(&characteristics.to_le_bytes());
+        bytes[0x58..0x5a].copy_from_slice(&PE32_PLUS_MAGIC.to_le_bytes());
+        bytes[0x58 + 108..0x58 + 112].copy_from_slice(&16u32.to_le_bytes());
+        bytes
+    }
+
+    /// Points the image's certificate table at `offset`, as signing would.
+    pub(crate) fn set_certificate_table(bytes: &mut [u8], offset: u32, length: u32) {
+        bytes[SECURITY_ENTRY..SECURITY_ENTRY + 4].copy_from_slice(&offset.to_le_bytes());
+        bytes[SECURITY_ENTRY + 4..SECURITY_ENTRY + 8].copy_from_slice(&length.to_le_bytes());
+    }
+
+    #[test]
+    fn reads_machine() {
+        let headers = PeHeaders::read(&mut Cursor::new(pe_bytes(0x8664, 0x22))).unwrap();
+        assert_eq!(headers.machine, Machine::Amd64);
+        assert_eq!(headers.characteristics, 0x22);
+        assert_eq!(headers.machine.to_string(), "x86_64");
+        assert!(headers.is_executable());
+        assert_eq!(headers.certificate_table, None);
+
+        let headers = PeHeaders::read(&mut Cursor::new(pe_bytes(0x1c0, 0))).unwrap();
+        assert_eq!(headers.machine, Machine::Unknown(0x1c0));
+        assert_eq!(headers.machine.to_string(), "unknown machine 0x01c0");
+        assert!(!headers.is_executable());
+
+        let headers = PeHeaders::read(&mut Cursor::new(pe_bytes(0xaa64, 0x2022))).unwrap();
+        assert_eq!(headers.machine.arch(), Some("aarch64"));
+        assert!(!headers.is_executable());
+    }
+
+    #[test]
+    fn reads_certificate_table() {
+        let mut bytes = pe_bytes(0x8664, 0x22);
+        set_certificate_table(&mut bytes, 0x200, 0x18);
+        let headers = PeHeaders::read(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(
+            headers.certificate_table,
+            Some(CertificateTable {
+                offset: 0x200,
+                length: 0x18
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        for bytes in &[
+            b"\x7fELF".to_vec(),
+            b"MZ".to_vec(),
+            pe_bytes(0x8664, 0)[..0x50].to_vec(),
+            {
+                let mut bytes = pe_bytes(0x8664, 0);
+                bytes[0x40] = b'X';
+                bytes
+            },
+        ] {
+            let result = PeHeaders::read(&mut Cursor::new(bytes));
+            assert_eq!(
+                result.err().map(|err| err.to_string()),
+                Some("This is not a PE image.".to_owned())
+            );
+        }
+    }
+}
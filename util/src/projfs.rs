@@ -1,5 +1,5 @@
 use {
-    crate::{raw_str_to_os_string, to_u16_vec, Error, ReadSeek, Result},
+    crate::{raw_str_to_os_string, to_u16_vec, zip::entry_modified, Error, ReadSeek, Result},
     lazy_static::lazy_static,
     log::{error, trace},
     std::{
@@ -632,18 +632,27 @@ fn create_file_basic_info(file: &ZipFile) -> PRJ_FILE_BASIC_INFO {
         FILE_ATTRIBUTE_NORMAL
     };
 
-    let large_int_zero = unsafe { mem::zeroed::<LARGE_INTEGER>() };
+    // Archives only record when entries were modified, so that stands in for
+    // every time.
+    let mut modified = unsafe { mem::zeroed::<LARGE_INTEGER>() };
+    unsafe { *modified.QuadPart_mut() = to_file_time(entry_modified(file)) };
     PRJ_FILE_BASIC_INFO {
         IsDirectory: file.is_dir().into(),
         FileSize: file.size() as i64,
-        CreationTime: large_int_zero,
-        LastAccessTime: large_int_zero,
-        LastWriteTime: large_int_zero,
-        ChangeTime: large_int_zero,
+        CreationTime: modified,
+        LastAccessTime: modified,
+        LastWriteTime: modified,
+        ChangeTime: modified,
         FileAttributes: attrs,
     }
 }
 
+/// Converts seconds since the Unix epoch to a Windows file time, which counts
+/// 100-nanosecond intervals since 1601.
+fn to_file_time(timestamp: i64) -> i64 {
+    (timestamp + 11_644_473_600) * 10_000_000
+}
+
 fn search_expression_ptr_to_option(p: *const u16) -> Option<OsString> {
     if p.is_null() || unsafe { *p == 0 } {
         None
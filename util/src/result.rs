@@ -32,14 +32,41 @@ impl error::Error for PoisonErrorInternal {
     }
 }
 
+/// An archive entry that would have been extracted outside of the directory
+/// it was meant to go in.
+#[derive(Debug)]
+pub struct UnsafePathError {
+    pub entry: String,
+    pub reason: &'static str,
+}
+impl fmt::Display for UnsafePathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Refusing to extract '{}' because it {}.",
+            self.entry, self.reason
+        )
+    }
+}
+impl error::Error for UnsafePathError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
 #[derive(Debug, ErrorEnum)]
 pub enum Error {
     Onex(ErrorInternal),
     Io(io::Error),
     Zip(ZipError),
     Walkdir(walkdir::Error),
+    Ignore(ignore::Error),
     StripPrefix(StripPrefixError),
     Poison(PoisonErrorInternal),
+    UnsafePath(UnsafePathError),
+    Signature(ed25519_dalek::SignatureError),
+    TomlDe(toml::de::Error),
+    TomlSer(toml::ser::Error),
 }
 
 impl From<&str> for Error {
@@ -0,0 +1,207 @@
+use {
+    crate::{
+        digest::{sha256_bytes, Digest, DIGEST_LENGTH},
+        result::Result,
+    },
+    sha2::{Digest as _, Sha256},
+    std::{convert::TryInto, fmt},
+};
+
+/// Raw IDs at or above this are free for apps to use however they like.
+const USER_SECTION_BASE: u32 = 0x8000_0000;
+
+/// Each directory entry is the section ID, four reserved bytes, the offset of
+/// the section relative to the end of the loader, its length and its digest.
+const ENTRY_LENGTH: usize = 4 + 4 + 8 + 8 + DIGEST_LENGTH;
+const COUNT_LENGTH: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SectionId {
+    /// The zip archive holding the app itself.
+    Payload,
+    Manifest,
+    Signature,
+    Resources,
+    /// A section defined by whoever packed the app, numbered from 0.
+    User(u32),
+    /// A section type added by a newer version of onex.
+    Unknown(u32),
+}
+
+impl SectionId {
+    pub fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => SectionId::Payload,
+            2 => SectionId::Manifest,
+            3 => SectionId::Signature,
+            4 => SectionId::Resources,
+            r if r >= USER_SECTION_BASE => SectionId::User(r - USER_SECTION_BASE),
+            r => SectionId::Unknown(r),
+        }
+    }
+
+    pub fn to_raw(self) -> u32 {
+        match self {
+            SectionId::Payload => 1,
+            SectionId::Manifest => 2,
+            SectionId::Signature => 3,
+            SectionId::Resources => 4,
+            SectionId::User(n) => USER_SECTION_BASE | n,
+            SectionId::Unknown(r) => r,
+        }
+    }
+}
+
+impl fmt::Display for SectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SectionId::Payload => "payload".fmt(f),
+            SectionId::Manifest => "manifest".fmt(f),
+            SectionId::Signature => "signature".fmt(f),
+            SectionId::Resources => "resources".fmt(f),
+            SectionId::User(n) => write!(f, "user:{}", n),
+            SectionId::Unknown(r) => write!(f, "unknown:{:#x}", r),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Section {
+    pub id: SectionId,
+    /// The offset of the section from the start of the file.
+    pub offset: u64,
+    pub length: u64,
+    pub digest: Option<Digest>,
+}
+
+impl Section {
+    pub fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+}
+
+/// Lays `contents` out one after another starting at `data_offset`.
+pub(crate) fn layout_sections(data_offset: u64, contents: &[(SectionId, Vec<u8>)]) -> Vec<Section> {
+    let mut offset = data_offset;
+    contents
+        .iter()
+        .map(|(id, bytes)| {
+            let section = Section {
+                id: *id,
+                offset,
+                length: bytes.len() as u64,
+                digest: Some(sha256_bytes(bytes)),
+            };
+            offset += section.length;
+            section
+        })
+        .collect()
+}
+
+pub(crate) fn directory_length(count: usize) -> u64 {
+    (ENTRY_LENGTH * count + COUNT_LENGTH) as u64
+}
+
+pub(crate) fn directory_bytes(sections: &[Section], data_offset: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(directory_length(sections.len()) as usize);
+    for section in sections {
+        bytes.extend(&section.id.to_raw().to_le_bytes());
+        bytes.extend(&0_u32.to_le_bytes());
+        bytes.extend(&(section.offset - data_offset).to_le_bytes());
+        bytes.extend(&section.length.to_le_bytes());
+        bytes.extend(&section.digest.unwrap_or_default());
+    }
+    bytes.extend(&(sections.len() as u32).to_le_bytes());
+
+    debug_assert_eq!(bytes.len() as u64, directory_length(sections.len()));
+    bytes
+}
+
+/// Reads the entry count from the last bytes of the data region, returning
+/// how long the whole directory is.
+pub(crate) fn parse_directory_length(count_bytes: &[u8]) -> u64 {
+    directory_length(u32::from_le_bytes(count_bytes.try_into().unwrap()) as usize)
+}
+
+/// Parses a directory whose entries must all fall within `data_length` bytes
+/// after `data_offset`.
+pub(crate) fn parse_directory(
+    bytes: &[u8],
+    data_offset: u64,
+    data_length: u64,
+) -> Result<Vec<Section>> {
+    let count = (bytes.len() - COUNT_LENGTH) / ENTRY_LENGTH;
+    (0..count)
+        .map(|i| {
+            let entry = &bytes[i * ENTRY_LENGTH..(i + 1) * ENTRY_LENGTH];
+            let relative_offset = u64::from_le_bytes(entry[8..16].try_into().unwrap());
+            let length = u64::from_le_bytes(entry[16..24].try_into().unwrap());
+
+            if relative_offset > data_length || length > data_length - relative_offset {
+                return Err("The onex section directory is corrupt.".into());
+            }
+
+            Ok(Section {
+                id: SectionId::from_raw(u32::from_le_bytes(entry[0..4].try_into().unwrap())),
+                offset: data_offset + relative_offset,
+                length,
+                digest: Some(entry[24..].try_into().unwrap()),
+            })
+        })
+        .collect()
+}
+
+/// The digest a signature covers: every section except the signature itself,
+/// identified by its ID, length and contents digest.
+pub(crate) fn directory_digest(sections: &[Section]) -> Digest {
+    let mut hasher = Sha256::new();
+    for section in sections.iter().filter(|s| s.id != SectionId::Signature) {
+        hasher.update(&section.id.to_raw().to_le_bytes());
+        hasher.update(&section.length.to_le_bytes());
+        hasher.update(&section.digest.unwrap_or_default());
+    }
+
+    let mut digest = [0; DIGEST_LENGTH];
+    digest.copy_from_slice(&hasher.finalize());
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn section_id_round_trip() {
+        for id in &[
+            SectionId::Payload,
+            SectionId::Manifest,
+            SectionId::Signature,
+            SectionId::Resources,
+            SectionId::User(0),
+            SectionId::User(42),
+            SectionId::Unknown(5),
+        ] {
+            assert_eq!(SectionId::from_raw(id.to_raw()), *id);
+        }
+    }
+
+    #[test]
+    fn directory_round_trip() {
+        let sections = layout_sections(
+            100,
+            &[
+                (SectionId::Payload, b"payload".to_vec()),
+                (SectionId::User(3), b"user".to_vec()),
+            ],
+        );
+        assert_eq!(sections[1].offset, 107);
+
+        let bytes = directory_bytes(&sections, 100);
+        assert_eq!(
+            parse_directory_length(&bytes[bytes.len() - COUNT_LENGTH..]),
+            bytes.len() as u64
+        );
+        assert_eq!(parse_directory(&bytes, 100, 11).unwrap(), sections);
+        assert!(parse_directory(&bytes, 100, 10).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use {
+    crate::{digest::Digest, result::Result},
+    ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer},
+    rand::rngs::OsRng,
+    std::convert::TryFrom,
+};
+
+pub use ed25519_dalek::{PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+
+/// Prepended to the payload digest before signing, so an onex signature can't
+/// be replayed as a signature over some other 32-byte message.
+const SIGNATURE_CONTEXT: &[u8] = b"onex payload signature v1\0";
+
+pub type PublicKeyBytes = [u8; PUBLIC_KEY_LENGTH];
+pub type SignatureBytes = [u8; SIGNATURE_LENGTH];
+
+/// An Ed25519 signature over a payload digest, along with the public key it
+/// was made with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PayloadSignature {
+    pub public_key: PublicKeyBytes,
+    pub signature: SignatureBytes,
+}
+
+/// Returns a new `(secret key, public key)` pair.
+pub fn generate_keypair() -> (Vec<u8>, PublicKeyBytes) {
+    let keypair = Keypair::generate(&mut OsRng);
+    (
+        keypair.secret.to_bytes().to_vec(),
+        keypair.public.to_bytes(),
+    )
+}
+
+pub fn sign_digest(secret_key: &[u8], digest: &Digest) -> Result<PayloadSignature> {
+    let secret = SecretKey::from_bytes(secret_key)?;
+    let public = PublicKey::from(&secret);
+    let keypair = Keypair { secret, public };
+
+    Ok(PayloadSignature {
+        public_key: public.to_bytes(),
+        signature: keypair.sign(&signed_message(digest)).to_bytes(),
+    })
+}
+
+pub fn verify_digest(public_key: &[u8], digest: &Digest, signature: &[u8]) -> Result<()> {
+    let public = PublicKey::from_bytes(public_key)?;
+    let signature = Signature::try_from(signature)?;
+
+    match public.verify_strict(&signed_message(digest), &signature) {
+        Ok(()) => Ok(()),
+        Err(_) => Err("The payload signature is not valid.".into()),
+    }
+}
+
+fn signed_message(digest: &Digest) -> Vec<u8> {
+    let mut message = SIGNATURE_CONTEXT.to_vec();
+    message.extend(digest);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() {
+        let (secret_key, public_key) = generate_keypair();
+        let digest = [7; 32];
+
+        let signature = sign_digest(&secret_key, &digest).unwrap();
+        assert_eq!(signature.public_key, public_key);
+        assert!(verify_digest(&public_key, &digest, &signature.signature).is_ok());
+
+        let mut other_digest = digest;
+        other_digest[0] = 8;
+        assert!(verify_digest(&public_key, &other_digest, &signature.signature).is_err());
+
+        let (_, other_public_key) = generate_keypair();
+        assert!(verify_digest(&other_public_key, &digest, &signature.signature).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use {
+    crate::{
+        digest::{sha256, Digest, DIGEST_LENGTH},
+        file::Trailer,
+        misc::OffsetWriter,
+        pe,
+        result::Result,
+        section::{self, Section, SectionId},
+    },
+    sha2::{Digest as _, Sha256},
+    std::io::{self, Read, Seek, SeekFrom, Write},
+};
+
+/// Builds an onex app by streaming the loader and each section straight to
+/// `w`, so nothing bigger than a copy buffer is ever held in memory.
+pub struct OnexWriter<W: Read + Write + Seek> {
+    w: W,
+    data_offset: u64,
+    loader_digest: Option<Digest>,
+    sections: Vec<Section>,
+}
+
+impl<W: Read + Write + Seek> OnexWriter<W> {
+    /// Starts a new app in `w`, which should be empty, beginning with the
+    /// loader read from `loader`.
+    pub fn new<R: Read>(mut w: W, loader: &mut R, digest_loader: bool) -> Result<Self> {
+        let data_offset = io::copy(loader, &mut w)?;
+        let loader_digest = if digest_loader {
+            Some(pe::image_digest(&mut w, data_offset)?)
+        } else {
+            None
+        };
+        Ok(OnexWriter {
+            w,
+            data_offset,
+            loader_digest,
+            sections: Vec::new(),
+        })
+    }
+
+    /// Appends a section holding everything read from `r`.
+    pub fn add_section<R: Read>(&mut self, id: SectionId, r: &mut R) -> Result<()> {
+        let offset = self.w.seek(SeekFrom::End(0))?;
+        let (length, digest) = copy_hashed(r, &mut self.w)?;
+        self.sections.push(Section {
+            id,
+            offset,
+            length,
+            digest: Some(digest),
+        });
+        Ok(())
+    }
+
+    /// Appends a section written by `write`, which may seek around within
+    /// it, as a zip writer does. The section is read back afterwards to
+    /// compute its digest.
+    pub fn write_section<F>(&mut self, id: SectionId, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut OffsetWriter<&mut W>) -> Result<()>,
+    {
+        let offset = self.w.seek(SeekFrom::End(0))?;
+        write(&mut OffsetWriter::new(&mut self.w)?)?;
+        let end = self.w.seek(SeekFrom::End(0))?;
+
+        self.w.seek(SeekFrom::Start(offset))?;
+        let digest = sha256(&mut (&mut self.w).take(end - offset))?;
+        self.sections.push(Section {
+            id,
+            offset,
+            length: end - offset,
+            digest: Some(digest),
+        });
+        Ok(())
+    }
+
+    /// Writes the section directory and the trailer, returning the finished
+    /// app.
+    pub fn finish(mut self) -> Result<W> {
+        let directory = section::directory_bytes(&self.sections, self.data_offset);
+        let sections_length: u64 = self.sections.iter().map(|s| s.length).sum();
+        let trailer = Trailer::new(
+            self.data_offset,
+            sections_length + directory.len() as u64,
+            self.loader_digest,
+        );
+
+        self.w.seek(SeekFrom::End(0))?;
+        self.w.write_all(&directory)?;
+        self.w.write_all(&trailer.to_bytes())?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+/// Copies all of `r` into `w`, returning how many bytes there were and their
+/// digest.
+fn copy_hashed<R: Read, W: Write>(r: &mut R, w: &mut W) -> Result<(u64, Digest)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    let mut length = 0;
+    loop {
+        let count = match r.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        hasher.update(&buffer[..count]);
+        w.write_all(&buffer[..count])?;
+        length += count as u64;
+    }
+
+    let mut digest = [0; DIGEST_LENGTH];
+    digest.copy_from_slice(&hasher.finalize());
+    Ok((length, digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{file::OnexFile, misc::SeekableVec},
+        std::io::Write,
+    };
+
+    #[test]
+    fn writer_matches_generated_bytes() {
+        let mut writer =
+            OnexWriter::new(SeekableVec::new(Vec::new()), &mut &b"loader"[..], true).unwrap();
+        writer
+            .add_section(SectionId::Payload, &mut &b"payload"[..])
+            .unwrap();
+        writer
+            .write_section(SectionId::Manifest, |w| {
+                w.write_all(b"maniXest")?;
+                w.seek(SeekFrom::Start(4))?;
+                w.write_all(b"f")?;
+                Ok(())
+            })
+            .unwrap();
+        let bytes = writer.finish().unwrap().into_vec();
+
+        let expected = OnexFile::generate_sections_bytes(
+            b"loader".to_vec(),
+            vec![
+                (SectionId::Payload, b"payload".to_vec()),
+                (SectionId::Manifest, b"manifest".to_vec()),
+            ],
+            true,
+        )
+        .unwrap();
+        assert_eq!(bytes, expected);
+    }
+}
@@ -1,83 +1,1695 @@
 use {
-    crate::{result::Result, SeekableVec},
-    ::zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipArchive, ZipWriter},
+    crate::{
+        result::{Result, UnsafePathError},
+        SeekableVec,
+    },
+    ::zip::{
+        read::ZipFile, result::ZipError, write::FileOptions, CompressionMethod, DateTime,
+        ZipArchive, ZipWriter,
+    },
+    chrono::{Local, TimeZone},
+    filetime::FileTime,
+    ignore::gitignore::GitignoreBuilder,
     std::{
-        fs::{create_dir_all, File},
+        collections::{HashMap, HashSet},
+        convert::TryFrom,
+        env,
+        fs::{self, create_dir_all, File},
         io::{self, Read, Seek, Write},
-        path::{Path, PathBuf},
+        path::{Component, Path, PathBuf},
+        time::UNIX_EPOCH,
     },
     walkdir::{self, WalkDir},
 };
 
-pub fn zip_app_dir(app_dir: &Path) -> Result<Vec<u8>> {
+#[cfg(unix)]
+use std::os::unix::fs::{symlink, PermissionsExt};
+
+/// A file in the root of an app listing, like a `.gitignore`, what not to
+/// pack.
+pub const IGNORE_FILE: &str = ".onexignore";
+
+/// Extensions of formats that are already compressed, which gain nothing from
+/// being compressed again.
+const COMPRESSED_EXTENSIONS: [&str; 16] = [
+    "7z", "bz2", "cab", "flac", "gif", "gz", "jpeg", "jpg", "mp3", "mp4", "ogg", "png", "webm",
+    "webp", "xz", "zip",
+];
+
+/// How the files in an app are compressed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Compression {
+    pub method: CompressionMethod,
+    /// The level to compress at with `method`, or its default if `None`.
+    pub level: Option<i32>,
+    /// Methods to use instead of `method` for files with these extensions,
+    /// which are lowercase and have no leading dot.
+    pub overrides: HashMap<String, CompressionMethod>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            method: CompressionMethod::Stored,
+            level: None,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl Compression {
+    /// Compresses with `method`, except for files that are already compressed,
+    /// which are stored.
+    pub fn new(method: CompressionMethod, level: Option<i32>) -> Result<Self> {
+        if method == CompressionMethod::Stored && level.is_some() {
+            return Err("A compression level can't be given for stored files.".into());
+        }
+
+        let overrides = if method == CompressionMethod::Stored {
+            HashMap::new()
+        } else {
+            COMPRESSED_EXTENSIONS
+                .iter()
+                .map(|ext| (ext.to_string(), CompressionMethod::Stored))
+                .collect()
+        };
+        Ok(Compression {
+            method,
+            level,
+            overrides,
+        })
+    }
+
+    /// Parses an override of the form `ext=method`.
+    pub fn add_override(&mut self, s: &str) -> Result<()> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(ext), Some(method)) if !ext.is_empty() => {
+                let ext = ext.trim_start_matches('.').to_lowercase();
+                self.overrides
+                    .insert(ext, parse_compression_method(method)?);
+                Ok(())
+            }
+            _ => Err(format!("'{}' should look like <extension>=<method>.", s).into()),
+        }
+    }
+
+    fn options_for(&self, path: &Path) -> FileOptions {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        let options = FileOptions::default();
+        match ext.and_then(|ext| self.overrides.get(&ext)) {
+            Some(method) => options.compression_method(*method),
+            None => options
+                .compression_method(self.method)
+                .compression_level(self.level),
+        }
+    }
+}
+
+/// Controls how `write_app_dir` archives an app.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ArchiveOptions {
+    pub compression: Compression,
+    /// If set, every entry is given this modification time, in seconds since
+    /// the Unix epoch, and the same permissions, so that packing the same
+    /// files always produces the same bytes. Files also get an extended
+    /// timestamp, since their zip headers are then in UTC rather than local
+    /// time.
+    pub reproducible_timestamp: Option<i64>,
+    /// If not empty, only files matching one of these gitignore-style
+    /// patterns are packed.
+    pub include: Vec<String>,
+    /// Files and directories matching any of these gitignore-style patterns
+    /// aren't packed, on top of those listed in the app's `.onexignore`.
+    pub exclude: Vec<String>,
+    /// Pack what symlinks point to rather than the symlinks themselves.
+    pub dereference_symlinks: bool,
+    /// Also record each file's modification time in an extended timestamp
+    /// extra field, which, unlike the zip header, isn't limited to even
+    /// seconds between 1980 and 2107.
+    pub extended_timestamps: bool,
+    /// Files that are never packed, such as the app being written, which are
+    /// compared by their canonical paths so it doesn't matter how they're
+    /// named.
+    pub skip_files: Vec<PathBuf>,
+}
+
+impl ArchiveOptions {
+    /// The timestamp to use for reproducible archives: `SOURCE_DATE_EPOCH` if
+    /// it's set, or else the earliest time a zip file can hold.
+    pub fn reproducible_timestamp() -> Result<i64> {
+        match env::var("SOURCE_DATE_EPOCH") {
+            Ok(epoch) => match epoch.trim().parse() {
+                Ok(epoch) => Ok(epoch),
+                Err(_) => Err(format!("SOURCE_DATE_EPOCH '{}' is not a number.", epoch).into()),
+            },
+            Err(_) => Ok(ZIP_EPOCH),
+        }
+    }
+
+    /// The modification time to record for `entry`, in seconds since the Unix
+    /// epoch.
+    fn modified_for(&self, entry: &AppDirEntry) -> Option<i64> {
+        self.reproducible_timestamp.or(entry.modified)
+    }
+
+    fn options_for(&self, entry: &AppDirEntry) -> Result<FileOptions> {
+        let options = match entry.kind {
+            AppDirEntryKind::File => self.compression.options_for(&entry.path),
+            _ => FileOptions::default(),
+        };
+        match (self.reproducible_timestamp, entry.mode) {
+            // Only keep whether the file is executable, since the rest of its
+            // mode depends on things like the umask it was created with. The
+            // timestamp is written in UTC rather than local time, so the
+            // archive doesn't depend on the time zone it was made in either.
+            (Some(timestamp), mode) => {
+                let mode = match (&entry.kind, mode) {
+                    (AppDirEntryKind::File, Some(mode)) if mode & 0o111 == 0 => 0o644,
+                    (AppDirEntryKind::File, None) => 0o644,
+                    _ => 0o755,
+                };
+                Ok(options
+                    .last_modified_time(zip_date_time(timestamp)?)
+                    .unix_permissions(mode))
+            }
+            (None, mode) => {
+                let options = match entry.modified {
+                    Some(modified) => options.last_modified_time(local_zip_date_time(modified)?),
+                    None => options,
+                };
+                Ok(match mode {
+                    Some(mode) => options.unix_permissions(mode),
+                    None => options,
+                })
+            }
+        }
+    }
+}
+
+/// 1980-01-01 00:00:00 UTC, the earliest time a zip entry can record.
+const ZIP_EPOCH: i64 = 315_532_800;
+
+/// 2107-12-31 23:59:58 UTC, the latest time a zip entry can record.
+const ZIP_END: i64 = 4_354_819_198;
+
+/// The ID of the extra field holding Info-ZIP's extended timestamps.
+const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+
+/// Converts seconds since the Unix epoch to a zip timestamp in UTC, clamping
+/// anything before 1980 to the start of 1980.
+fn zip_date_time(timestamp: i64) -> Result<DateTime> {
+    let timestamp = timestamp.max(ZIP_EPOCH);
+    let (year, month, day, hour, minute, second) = civil_from_timestamp(timestamp);
+    match DateTime::from_date_and_time(
+        year as u16,
+        month as u8,
+        day as u8,
+        hour as u8,
+        minute as u8,
+        second as u8,
+    ) {
+        Ok(date_time) => Ok(date_time),
+        Err(()) => Err(format!("The timestamp {} is too late for a zip file.", timestamp).into()),
+    }
+}
+
+/// Converts seconds since the Unix epoch to a zip timestamp in local time,
+/// which is how other zip tools read them, clamping it to the range a zip
+/// timestamp can hold.
+fn local_zip_date_time(timestamp: i64) -> Result<DateTime> {
+    zip_date_time((timestamp + utc_offset(timestamp)).min(ZIP_END))
+}
+
+/// The inverse of `local_zip_date_time`. Times that happen twice, or not at
+/// all, as clocks change are taken to be in the offset in force just before.
+fn timestamp_from_local_civil(date_time: &DateTime) -> i64 {
+    let local = timestamp_from_civil(date_time);
+    local - utc_offset(local - utc_offset(local))
+}
+
+/// How many seconds ahead of UTC the local time zone is at `timestamp`.
+fn utc_offset(timestamp: i64) -> i64 {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map_or(0, |date_time| date_time.offset().local_minus_utc() as i64)
+}
+
+/// Splits seconds since the Unix epoch into a UTC year, month, day, hour,
+/// minute and second.
+fn civil_from_timestamp(timestamp: i64) -> (i64, i64, i64, i64, i64, i64) {
+    let (days, seconds) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+
+    // Converts days since the epoch to a civil date, as in
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+    )
+}
+
+/// The inverse of `civil_from_timestamp`, as in
+/// http://howardhinnant.github.io/date_algorithms.html#days_from_civil.
+fn timestamp_from_civil(date_time: &DateTime) -> i64 {
+    let (month, day) = (date_time.month() as i64, date_time.day() as i64);
+    let year = date_time.year() as i64 - if month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    days * 86400
+        + date_time.hour() as i64 * 3600
+        + date_time.minute() as i64 * 60
+        + date_time.second() as i64
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let (year, month, day, hour, minute, second) = civil_from_timestamp(timestamp);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// When `entry` was last modified, in seconds since the Unix epoch. This comes
+/// from its extended timestamp if it has one, or else from its zip header,
+/// which is in local time.
+pub(crate) fn entry_modified(entry: &ZipFile) -> i64 {
+    extended_timestamp(entry.extra_data())
+        .unwrap_or_else(|| timestamp_from_local_civil(&entry.last_modified()))
+}
+
+/// Finds the modification time in an extended timestamp extra field.
+fn extended_timestamp(mut extra_data: &[u8]) -> Option<i64> {
+    while extra_data.len() >= 4 {
+        let id = u16::from_le_bytes([extra_data[0], extra_data[1]]);
+        let length = u16::from_le_bytes([extra_data[2], extra_data[3]]) as usize;
+        let data = extra_data.get(4..4 + length)?;
+        if id == EXTENDED_TIMESTAMP_ID && data.len() >= 5 && data[0] & 1 != 0 {
+            return Some(i32::from_le_bytes([data[1], data[2], data[3], data[4]]) as i64);
+        }
+        extra_data = &extra_data[4 + length..];
+    }
+    None
+}
+
+pub fn parse_compression_method(s: &str) -> Result<CompressionMethod> {
+    match s {
+        "stored" => Ok(CompressionMethod::Stored),
+        "deflate" => Ok(CompressionMethod::Deflated),
+        "bzip2" => Ok(CompressionMethod::Bzip2),
+        "zstd" => Ok(CompressionMethod::Zstd),
+        _ => Err(format!("'{}' is not a supported compression method.", s).into()),
+    }
+}
+
+/// Zips up everything in `app_dir`. If an `app_entry` is given, it must name a
+/// file somewhere in the tree.
+pub fn zip_app_dir(app_dir: &Path, app_entry: Option<&Path>) -> Result<Vec<u8>> {
+    let output_bytes = write_app_dir(
+        app_dir,
+        app_entry,
+        &ArchiveOptions::default(),
+        SeekableVec::new(Vec::new()),
+    )?;
+    Ok(output_bytes.into_vec())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AppDirEntryKind {
+    File,
+    Directory,
+    /// A symlink to the given target, with `/` separating directories.
+    Symlink(String),
+}
+
+/// A file, directory or symlink in an app that will be packed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AppDirEntry {
+    pub path: PathBuf,
+    /// The path relative to the app directory, which is what the entry is
+    /// named in the archive, with `/` separating directories.
+    pub name: String,
+    pub kind: AppDirEntryKind,
+    /// The Unix permission bits, on platforms that have them.
+    pub mode: Option<u32>,
+    /// When the entry was last modified, in seconds since the Unix epoch.
+    pub modified: Option<i64>,
+}
+
+impl AppDirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.kind == AppDirEntryKind::Directory
+    }
+}
+
+/// Lists everything in `app_dir` that `write_app_dir` would pack, in the
+/// order it would pack them.
+pub fn app_dir_entries(app_dir: &Path, options: &ArchiveOptions) -> Result<Vec<AppDirEntry>> {
     if !app_dir.is_dir() {
         return Err(ZipError::FileNotFound.into());
     }
 
-    let mut output_bytes = SeekableVec::new(Vec::new());
-    let mut zip = ZipWriter::new(&mut output_bytes);
-    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut excluded = GitignoreBuilder::new(app_dir);
+    let ignore_file = app_dir.join(IGNORE_FILE);
+    if ignore_file.is_file() {
+        if let Some(err) = excluded.add(&ignore_file) {
+            return Err(err.into());
+        }
+    }
+    for pattern in &options.exclude {
+        excluded.add_line(None, pattern)?;
+    }
+    let excluded = excluded.build()?;
+
+    let mut included = GitignoreBuilder::new(app_dir);
+    for pattern in &options.include {
+        included.add_line(None, pattern)?;
+    }
+    let included = included.build()?;
+
+    // Files that don't exist yet can't be walked into either.
+    let skipped = options
+        .skip_files
+        .iter()
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect::<HashSet<_>>();
+
+    let walker = WalkDir::new(app_dir)
+        .min_depth(1)
+        .follow_links(options.dereference_symlinks)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_entry(|entry| {
+            let is_dir = entry.file_type().is_dir();
+            !excluded.matched(entry.path(), is_dir).is_ignore()
+        });
 
-    for entry_result in WalkDir::new(app_dir) {
+    let mut entries = Vec::new();
+    for entry_result in walker {
         let entry = entry_result?;
         let path = entry.path();
         let stripped_path = path.strip_prefix(app_dir)?;
-        let name = stripped_path.to_string_lossy();
+        let kind = if entry.file_type().is_symlink() {
+            AppDirEntryKind::Symlink(symlink_target(path)?)
+        } else if entry.file_type().is_dir() {
+            AppDirEntryKind::Directory
+        } else {
+            AppDirEntryKind::File
+        };
+        let is_dir = kind == AppDirEntryKind::Directory;
+        if !is_dir && stripped_path == Path::new(IGNORE_FILE) {
+            continue;
+        }
+        if !is_dir
+            && !options.include.is_empty()
+            && !included
+                .matched_path_or_any_parents(stripped_path, false)
+                .is_ignore()
+        {
+            continue;
+        }
+        // Only regular files can be the output, and entries that can't be
+        // resolved (like dangling symlinks) are never skipped.
+        if entry.file_type().is_file()
+            && !skipped.is_empty()
+            && matches!(fs::canonicalize(path), Ok(path) if skipped.contains(&path))
+        {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        entries.push(AppDirEntry {
+            path: path.to_owned(),
+            name: stripped_path
+                .iter()
+                .map(|component| component.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            kind,
+            mode: unix_mode(&metadata),
+            modified: modified_timestamp(&metadata),
+        });
+    }
 
-        if path.is_file() {
-            zip.start_file(name, options)?;
-            let mut f = File::open(path)?;
+    // Only keep the directories that something was included from.
+    if !options.include.is_empty() {
+        let used_dirs = entries
+            .iter()
+            .filter(|entry| !entry.is_dir())
+            .flat_map(|entry| Path::new(&entry.name).ancestors().skip(1))
+            .map(|dir| dir.to_owned())
+            .collect::<HashSet<_>>();
+        entries.retain(|entry| !entry.is_dir() || used_dirs.contains(Path::new(&entry.name)));
+    }
 
-            let mut buffer = Vec::new();
-            f.read_to_end(&mut buffer)?;
-            zip.write_all(&buffer)?;
-        } else if name.len() != 0 {
-            zip.add_directory(name, options)?;
+    Ok(entries)
+}
+
+/// Like `zip_app_dir`, but streams the archive into `w` one file at a time,
+/// as `options` says. Entries are always added in order of their names, with
+/// `/` separating directories.
+pub fn write_app_dir<W: Write + Seek>(
+    app_dir: &Path,
+    app_entry: Option<&Path>,
+    options: &ArchiveOptions,
+    w: W,
+) -> Result<W> {
+    let entries = app_dir_entries(app_dir, options)?;
+    if let Some(app_entry) = app_entry {
+        // Leading `.` components would otherwise stop it matching.
+        let normalized = app_entry
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .collect::<PathBuf>();
+        let packed = entries.iter().any(|entry| {
+            !entry.is_dir() && entry.path.strip_prefix(app_dir) == Ok(normalized.as_path())
+        });
+        if !packed {
+            return Err(format!(
+                "The entry '{}' is not a file that would be packed from {}.",
+                app_entry.display(),
+                app_dir.display()
+            )
+            .into());
         }
     }
-    zip.finish()?;
 
-    drop(zip);
-    Ok(output_bytes.into_vec())
+    let mut zip = ZipWriter::new(w);
+    for entry in entries {
+        let entry_options = options.options_for(&entry)?;
+        match &entry.kind {
+            AppDirEntryKind::File => {
+                let file_options =
+                    entry_options.large_file(entry.path.metadata()?.len() >= u32::MAX as u64);
+                let extended_timestamp = options
+                    .modified_for(&entry)
+                    .and_then(|modified| i32::try_from(modified).ok())
+                    .filter(|_| {
+                        options.extended_timestamps || options.reproducible_timestamp.is_some()
+                    });
+                match extended_timestamp {
+                    Some(modified) => {
+                        zip.start_file_with_extra_data(entry.name, file_options)?;
+                        zip.write_all(&EXTENDED_TIMESTAMP_ID.to_le_bytes())?;
+                        zip.write_all(&5u16.to_le_bytes())?;
+                        zip.write_all(&[1])?;
+                        zip.write_all(&modified.to_le_bytes())?;
+                        zip.end_extra_data()?;
+                    }
+                    None => zip.start_file(entry.name, file_options)?,
+                }
+                let mut f = File::open(&entry.path)?;
+                io::copy(&mut f, &mut zip)?;
+            }
+            AppDirEntryKind::Directory => zip.add_directory(entry.name, entry_options)?,
+            AppDirEntryKind::Symlink(target) => {
+                zip.add_symlink(entry.name, target.clone(), entry_options)?
+            }
+        }
+    }
+
+    Ok(zip.finish()?)
+}
+
+fn symlink_target(path: &Path) -> Result<String> {
+    let target = fs::read_link(path)?.to_string_lossy().into_owned();
+    Ok(if cfg!(windows) {
+        target.replace('\\', "/")
+    } else {
+        target
+    })
+}
+
+fn modified_timestamp(metadata: &fs::Metadata) -> Option<i64> {
+    let modified = metadata.modified().ok()?;
+    Some(match modified.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    })
+}
+
+#[cfg(unix)]
+fn unix_mode(metadata: &fs::Metadata) -> Option<u32> {
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn unix_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// What to do with archive entries whose names would put them outside of the
+/// directory they're extracted to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnsafePathPolicy {
+    /// Fail before extracting anything.
+    Reject,
+    /// Drop any root, drive prefix and `..` components from the name, and
+    /// extract what's left. Entries that escape through a symlink are still
+    /// rejected.
+    Sanitize,
+}
+
+impl Default for UnsafePathPolicy {
+    fn default() -> Self {
+        UnsafePathPolicy::Reject
+    }
+}
+
+/// What to do when a file being extracted already exists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExistingFilePolicy {
+    /// Replace it.
+    Overwrite,
+    /// Leave it alone and move on.
+    Skip,
+    /// Fail before extracting anything.
+    Fail,
+}
+
+impl Default for ExistingFilePolicy {
+    fn default() -> Self {
+        ExistingFilePolicy::Overwrite
+    }
+}
+
+/// Controls what `extract_entries` extracts and where it puts it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExtractOptions {
+    pub unsafe_paths: UnsafePathPolicy,
+    /// If not empty, only entries matching one of these gitignore-style
+    /// patterns, or inside a directory that does, are extracted.
+    pub patterns: Vec<String>,
+    /// How many leading directories to drop from each entry's path. Entries
+    /// with nothing left are skipped.
+    pub strip_components: usize,
+    pub existing_files: ExistingFilePolicy,
+}
+
+/// Extracts the archive into `output_path`, restoring modification times. On
+/// Unix, permissions and symlinks are restored too; elsewhere symlinks become
+/// files holding their targets.
+pub fn extract_zip<S: Read + Seek>(
+    seeker: S,
+    output_path: &Path,
+    policy: UnsafePathPolicy,
+) -> Result<()> {
+    extract_entries(
+        seeker,
+        output_path,
+        &ExtractOptions {
+            unsafe_paths: policy,
+            ..Default::default()
+        },
+    )
 }
 
-pub fn extract_zip<S: Read + Seek>(seeker: S, output_path: &Path) -> Result<()> {
+/// Like `extract_zip`, but only extracts what `options` selects.
+pub fn extract_entries<S: Read + Seek>(
+    seeker: S,
+    output_path: &Path,
+    options: &ExtractOptions,
+) -> Result<()> {
     let mut archive = ZipArchive::new(seeker)?;
+    let policy = options.unsafe_paths;
+
+    let mut selected = GitignoreBuilder::new(output_path);
+    for pattern in &options.patterns {
+        selected.add_line(None, pattern)?;
+    }
+    let selected = selected.build()?;
+
+    // Check every name and symlink up front, so a malicious archive is
+    // rejected before anything is written.
+    let selected_entries = (0..archive.len())
+        .map(|i| {
+            let mut entry = archive.by_index(i)?;
+            let relative_path = match relative_entry_path(entry.name(), policy)? {
+                Some(relative_path) => relative_path,
+                None => return Ok(None),
+            };
+            if !options.patterns.is_empty()
+                && !selected
+                    .matched_path_or_any_parents(&relative_path, entry.is_dir())
+                    .is_ignore()
+            {
+                return Ok(None);
+            }
+            let relative_path = match strip_components(&relative_path, options.strip_components) {
+                Some(relative_path) => relative_path,
+                None => return Ok(None),
+            };
+
+            let symlink_target = if is_symlink(&entry) {
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                Some(target)
+            } else {
+                None
+            };
+            if options.existing_files == ExistingFilePolicy::Fail
+                && entry.is_file()
+                && output_path.join(&relative_path).symlink_metadata().is_ok()
+            {
+                return Err(format!(
+                    "{} already exists.",
+                    output_path.join(&relative_path).display()
+                )
+                .into());
+            }
+            Ok(Some((
+                relative_path,
+                entry.name().to_owned(),
+                symlink_target,
+            )))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Symlinks are checked against each other, and any already in the output
+    // directory, since one can lead somewhere else through another.
+    let symlinks = selected_entries
+        .iter()
+        .flatten()
+        .filter(|(_, _, target)| target.is_some())
+        .map(|(relative_path, _, _)| relative_path.clone())
+        .collect::<HashSet<_>>();
+    let is_extracted_symlink = |relative_path: &Path| {
+        symlinks.contains(relative_path)
+            || matches!(
+                output_path.join(relative_path).symlink_metadata(),
+                Ok(metadata) if metadata.file_type().is_symlink()
+            )
+    };
+    let relative_paths = selected_entries
+        .into_iter()
+        .map(|entry| match entry {
+            Some((relative_path, name, Some(target)))
+                if !symlink_stays_inside(&relative_path, &target, &is_extracted_symlink) =>
+            {
+                match policy {
+                    UnsafePathPolicy::Reject => Err(UnsafePathError {
+                        entry: name,
+                        reason: "is a symlink leading outside the output directory",
+                    }
+                    .into()),
+                    UnsafePathPolicy::Sanitize => Ok(None),
+                }
+            }
+            entry => Ok(entry.map(|(relative_path, _, _)| relative_path)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if !options.patterns.is_empty() && relative_paths.iter().all(Option::is_none) {
+        return Err("Nothing in the archive matches the given patterns.".into());
+    }
 
-    for i in 0..archive.len() {
+    create_dir_all(output_path)?;
+    let root = output_path.canonicalize()?;
+
+    let mut dirs = Vec::new();
+    for (i, relative_path) in relative_paths.into_iter().enumerate() {
+        let relative_path = match relative_path {
+            Some(relative_path) => relative_path,
+            None => continue,
+        };
         let mut entry = archive.by_index(i)?;
-        let entry_output_path: PathBuf =
-            [output_path, &PathBuf::from(entry.name())].iter().collect();
+        let entry_output_path = root.join(relative_path);
 
         if entry.is_file() {
             let parent = entry_output_path.parent().unwrap();
-            if !parent.exists() {
-                create_dir_all(&parent)?;
+            check_inside(&root, parent, entry.name())?;
+            create_dir_all(&parent)?;
+            check_inside(&root, &entry_output_path, entry.name())?;
+
+            // Replace symlinks rather than writing through them.
+            if let Ok(metadata) = entry_output_path.symlink_metadata() {
+                if options.existing_files == ExistingFilePolicy::Skip {
+                    continue;
+                }
+                if metadata.file_type().is_symlink() || is_symlink(&entry) {
+                    fs::remove_file(&entry_output_path)?;
+                }
             }
 
-            let mut output_file = File::create(&entry_output_path)?;
-            io::copy(&mut entry, &mut output_file)?;
+            if cfg!(unix) && is_symlink(&entry) {
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                create_symlink(&target, &entry_output_path)?;
+            } else {
+                let mut output_file = File::create(&entry_output_path)?;
+                io::copy(&mut entry, &mut output_file)?;
+                drop(output_file);
+                set_modified(&entry_output_path, entry_modified(&entry))?;
+                if let Some(mode) = entry.unix_mode() {
+                    set_mode(&entry_output_path, mode)?;
+                }
+            }
         } else {
+            check_inside(&root, &entry_output_path, entry.name())?;
             create_dir_all(&entry_output_path)?;
+            dirs.push((entry_output_path, entry_modified(&entry), entry.unix_mode()));
+        }
+    }
+
+    // Directories get their times and modes last, since writing into them
+    // changes the former and the latter might not allow writing.
+    for (path, modified, mode) in dirs.into_iter().rev() {
+        set_modified(&path, modified)?;
+        if let Some(mode) = mode {
+            set_mode(&path, mode)?;
         }
     }
 
     Ok(())
 }
 
-pub fn list_zip_contents<S: Read + Seek>(seeker: S) -> Result<()> {
+fn is_symlink(entry: &ZipFile) -> bool {
+    entry
+        .unix_mode()
+        .map(|mode| mode & 0o170000 == 0o120000)
+        .unwrap_or(false)
+}
+
+/// Whether a symlink at `relative_path` pointing to `target` stays inside the
+/// directory `relative_path` is relative to. `is_symlink` says whether a path
+/// relative to that directory is a symlink, since `..` after going through one
+/// leads up from wherever it points to rather than from the symlink itself.
+fn symlink_stays_inside(
+    relative_path: &Path,
+    target: &str,
+    is_symlink: &dyn Fn(&Path) -> bool,
+) -> bool {
+    if target.starts_with('/') || target.starts_with('\\') || cfg!(windows) && target.contains(':')
+    {
+        return false;
+    }
+
+    let mut resolved = PathBuf::new();
+    let mut through_symlink = false;
+    for component in relative_path
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+    {
+        resolved.push(component);
+        through_symlink |= is_symlink(&resolved);
+    }
+    for component in target.split(&['/', '\\'][..]) {
+        match component {
+            "" | "." => (),
+            ".." => {
+                if through_symlink || !resolved.pop() {
+                    return false;
+                }
+            }
+            _ => {
+                resolved.push(component);
+                through_symlink |= is_symlink(&resolved);
+            }
+        }
+    }
+    true
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> Result<()> {
+    Ok(symlink(target, path)?)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(target: &str, path: &Path) -> Result<()> {
+    Ok(fs::write(path, target)?)
+}
+
+fn set_modified(path: &Path, modified: i64) -> Result<()> {
+    Ok(filetime::set_file_mtime(
+        path,
+        FileTime::from_unix_time(modified, 0),
+    )?)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    Ok(fs::set_permissions(
+        path,
+        fs::Permissions::from_mode(mode & 0o7777),
+    )?)
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// Turns an entry name into a path relative to the output directory, or
+/// `None` if sanitizing leaves nothing of it. Both `/` and `\\` are treated as
+/// separators, since archives made on Windows sometimes use the latter.
+/// Colons are only unsafe on Windows, where they start a drive prefix or name
+/// an alternate data stream, and are ordinary characters elsewhere.
+fn relative_entry_path(name: &str, policy: UnsafePathPolicy) -> Result<Option<PathBuf>> {
+    let mut path = PathBuf::new();
+    for (i, component) in name.split(&['/', '\\'][..]).enumerate() {
+        let reason = match component {
+            "" if i == 0 && !name.is_empty() => Some("is an absolute path"),
+            "" | "." => continue,
+            ".." => Some("refers to a parent directory"),
+            c if cfg!(windows) && i == 0 && has_drive_prefix(c) => Some("has a drive prefix"),
+            c if cfg!(windows) && c.contains(':') => Some("has a stream name"),
+            _ => None,
+        };
+
+        match (reason, policy) {
+            (Some(reason), UnsafePathPolicy::Reject) => {
+                return Err(UnsafePathError {
+                    entry: name.to_owned(),
+                    reason,
+                }
+                .into())
+            }
+            (Some(_), UnsafePathPolicy::Sanitize) => (),
+            (None, _) => path.push(component),
+        }
+    }
+
+    Ok(if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    })
+}
+
+fn has_drive_prefix(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Drops the first `count` components of `path`, or returns `None` if that
+/// leaves nothing.
+fn strip_components(path: &Path, count: usize) -> Option<PathBuf> {
+    let stripped = path.components().skip(count).collect::<PathBuf>();
+    if stripped.as_os_str().is_empty() {
+        None
+    } else {
+        Some(stripped)
+    }
+}
+
+/// Checks that `path`, or as much of it as already exists, doesn't lead out of
+/// `root` through a symlink.
+fn check_inside(root: &Path, path: &Path, entry_name: &str) -> Result<()> {
+    let mut existing = path;
+    while existing.symlink_metadata().is_err() {
+        existing = match existing.parent() {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+
+    match existing.canonicalize() {
+        Ok(canonical) if canonical.starts_with(root) => Ok(()),
+        _ => Err(UnsafePathError {
+            entry: entry_name.to_owned(),
+            reason: "would be written through a symlink that leads elsewhere",
+        }
+        .into()),
+    }
+}
+
+/// What an archive entry is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArchiveEntryKind {
+    File,
+    Directory,
+    /// A symlink to the given target.
+    Symlink(String),
+}
+
+/// An entry in an app's archive, as `list_entries` describes it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveEntry {
+    /// The entry's name in the archive, with `/` separating directories.
+    pub path: String,
+    pub kind: ArchiveEntryKind,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub compression: CompressionMethod,
+    pub crc32: u32,
+    /// When the entry was last modified, in seconds since the Unix epoch.
+    pub modified: i64,
+    /// The Unix permission bits, if the archive recorded them.
+    pub mode: Option<u32>,
+}
+
+impl ArchiveEntry {
+    pub fn is_dir(&self) -> bool {
+        self.kind == ArchiveEntryKind::Directory
+    }
+}
+
+/// Describes every entry in the archive, in the order they're stored.
+pub fn list_entries<S: Read + Seek>(seeker: S) -> Result<Vec<ArchiveEntry>> {
     let mut archive = ZipArchive::new(seeker)?;
 
-    for i in 0..archive.len() {
-        let entry = archive.by_index(i)?;
-        let name = entry.name().replace("/", "\\");
-        println!(
-            "{} ({}/{} bytes compressed/uncompressed)",
-            name,
-            entry.compressed_size(),
-            entry.size()
+    (0..archive.len())
+        .map(|i| {
+            let mut entry = archive.by_index(i)?;
+            let kind = if entry.is_dir() {
+                ArchiveEntryKind::Directory
+            } else if is_symlink(&entry) {
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                ArchiveEntryKind::Symlink(target)
+            } else {
+                ArchiveEntryKind::File
+            };
+            Ok(ArchiveEntry {
+                path: entry.name().to_owned(),
+                kind,
+                size: entry.size(),
+                compressed_size: entry.compressed_size(),
+                compression: entry.compression(),
+                crc32: entry.crc32(),
+                modified: entry_modified(&entry),
+                mode: entry.unix_mode().map(|mode| mode & 0o7777),
+            })
+        })
+        .collect()
+}
+
+/// Opens the file at `path` in the archive for reading. Either `/` or `\\` can
+/// separate directories.
+pub fn open_entry<'a, S: Read + Seek>(
+    archive: &'a mut ZipArchive<S>,
+    path: &str,
+) -> Result<ZipFile<'a>> {
+    let name = path
+        .split(&['/', '\\'][..])
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect::<Vec<_>>()
+        .join("/");
+    if archive.by_name(&format!("{}/", name)).is_ok() {
+        return Err(format!("'{}' is a directory.", path).into());
+    }
+    match archive.by_name(&name) {
+        Ok(entry) => Ok(entry),
+        Err(ZipError::FileNotFound) => Err(format!("'{}' is not in the archive.", path).into()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The name `parse_compression_method` knows `method` by.
+pub fn compression_method_name(method: CompressionMethod) -> String {
+    match method {
+        CompressionMethod::Stored => "stored".to_owned(),
+        CompressionMethod::Deflated => "deflate".to_owned(),
+        CompressionMethod::Bzip2 => "bzip2".to_owned(),
+        CompressionMethod::Zstd => "zstd".to_owned(),
+        method => format!("{:?}", method).to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        lazy_static::lazy_static,
+        std::{fs, sync::Mutex},
+    };
+
+    lazy_static! {
+        /// Held by tests that depend on the local time zone, so the one that
+        /// changes it doesn't pull it out from under the others.
+        static ref TIME_ZONE: Mutex<()> = Mutex::new(());
+    }
+
+    fn lock_time_zone() -> std::sync::MutexGuard<'static, ()> {
+        TIME_ZONE.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    #[test]
+    fn zip_app_dir_checks_entry() {
+        let app_dir = std::env::temp_dir().join("onex_zip_app_dir_checks_entry");
+        fs::create_dir_all(app_dir.join("bin")).unwrap();
+        fs::write(app_dir.join("bin").join("app.exe"), b"app").unwrap();
+
+        assert!(zip_app_dir(&app_dir, None).is_ok());
+        assert!(zip_app_dir(&app_dir, Some(Path::new("bin/app.exe"))).is_ok());
+        assert!(zip_app_dir(&app_dir, Some(Path::new("./bin/./app.exe"))).is_ok());
+        assert!(zip_app_dir(&app_dir, Some(Path::new("bin"))).is_err());
+        assert!(zip_app_dir(&app_dir, Some(Path::new("app.exe"))).is_err());
+
+        fs::remove_dir_all(&app_dir).unwrap();
+    }
+
+    #[test]
+    fn write_app_dir_compresses() {
+        let app_dir = std::env::temp_dir().join("onex_write_app_dir_compresses");
+        fs::create_dir_all(&app_dir).unwrap();
+        let contents = "onex ".repeat(1000);
+        fs::write(app_dir.join("app.txt"), &contents).unwrap();
+        fs::write(app_dir.join("photo.JPG"), &contents).unwrap();
+
+        for method in &["deflate", "bzip2", "zstd"] {
+            let options = ArchiveOptions {
+                compression: Compression::new(parse_compression_method(method).unwrap(), None)
+                    .unwrap(),
+                ..Default::default()
+            };
+            let bytes = write_app_dir(&app_dir, None, &options, SeekableVec::new(Vec::new()))
+                .unwrap()
+                .into_vec();
+
+            let mut archive = ZipArchive::new(SeekableVec::new(bytes)).unwrap();
+            let mut text = archive.by_name("app.txt").unwrap();
+            assert_eq!(compression_method_name(text.compression()), *method);
+            assert!(text.compressed_size() < text.size());
+            let mut read = String::new();
+            text.read_to_string(&mut read).unwrap();
+            assert_eq!(read, contents);
+            drop(text);
+
+            let photo = archive.by_name("photo.JPG").unwrap();
+            assert_eq!(photo.compression(), CompressionMethod::Stored);
+        }
+
+        let mut compression = Compression::default();
+        compression.add_override(".TXT=zstd").unwrap();
+        assert_eq!(compression.overrides["txt"], CompressionMethod::Zstd);
+
+        assert!(Compression::new(CompressionMethod::Stored, Some(3)).is_err());
+        assert!(Compression::default().add_override("=zstd").is_err());
+        assert!(Compression::default().add_override("txt=lzma").is_err());
+
+        fs::remove_dir_all(&app_dir).unwrap();
+    }
+
+    #[test]
+    fn write_app_dir_skips_its_output() {
+        let app_dir = std::env::temp_dir().join("onex_write_app_dir_skips_its_output");
+        fs::create_dir_all(&app_dir).unwrap();
+        fs::write(app_dir.join("app.txt"), "onex ".repeat(1000)).unwrap();
+
+        let output_path = app_dir.join("app.exe");
+        let output_file = File::create(&output_path).unwrap();
+        let options = ArchiveOptions {
+            skip_files: vec![app_dir.join(".").join("app.exe")],
+            ..Default::default()
+        };
+        write_app_dir(&app_dir, None, &options, output_file).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output_path).unwrap()).unwrap();
+        let names = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["app.txt"]);
+
+        fs::remove_dir_all(&app_dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn write_app_dir_keeps_dangling_symlinks_when_skipping() {
+        let app_dir = std::env::temp_dir().join("onex_write_app_dir_keeps_dangling_symlinks");
+        let _ = fs::remove_dir_all(&app_dir);
+        fs::create_dir_all(&app_dir).unwrap();
+        fs::write(app_dir.join("app.txt"), "onex").unwrap();
+        symlink("missing.txt", app_dir.join("dangling")).unwrap();
+
+        let output_path = std::env::temp_dir().join("onex_write_app_dir_keeps_dangling.zip");
+        let output_file = File::create(&output_path).unwrap();
+        let options = ArchiveOptions {
+            skip_files: vec![output_path.clone()],
+            ..Default::default()
+        };
+        write_app_dir(&app_dir, None, &options, output_file).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output_path).unwrap()).unwrap();
+        let names = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["app.txt", "dangling"]);
+
+        fs::remove_dir_all(&app_dir).unwrap();
+        fs::remove_file(&output_path).unwrap();
+    }
+
+    #[test]
+    fn zip_date_time_converts_epoch_seconds() {
+        let date_time = zip_date_time(1_600_000_000).unwrap();
+        assert_eq!(
+            (
+                date_time.year(),
+                date_time.month(),
+                date_time.day(),
+                date_time.hour(),
+                date_time.minute(),
+                date_time.second()
+            ),
+            (2020, 9, 13, 12, 26, 40)
         );
+        assert_eq!(zip_date_time(0).unwrap().year(), 1980);
+        assert_eq!(zip_date_time(951_782_400).unwrap().month(), 2);
+        assert_eq!(zip_date_time(951_782_400).unwrap().day(), 29);
     }
 
-    Ok(())
+    #[test]
+    fn write_app_dir_is_reproducible() {
+        let app_dir = std::env::temp_dir().join("onex_write_app_dir_is_reproducible");
+        fs::create_dir_all(app_dir.join("b")).unwrap();
+        fs::write(app_dir.join("b").join("c.txt"), b"c").unwrap();
+        fs::write(app_dir.join("a.txt"), b"a").unwrap();
+
+        let options = ArchiveOptions {
+            reproducible_timestamp: Some(1_600_000_000),
+            ..Default::default()
+        };
+        let pack = || {
+            write_app_dir(&app_dir, None, &options, SeekableVec::new(Vec::new()))
+                .unwrap()
+                .into_vec()
+        };
+        let first = pack();
+        fs::write(app_dir.join("a.txt"), b"a").unwrap();
+        assert_eq!(pack(), first);
+
+        let mut archive = ZipArchive::new(SeekableVec::new(first)).unwrap();
+        let names = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a.txt", "b/", "b/c.txt"]);
+        assert_eq!(
+            archive.by_name("a.txt").unwrap().unix_mode(),
+            Some(0o100644)
+        );
+
+        fs::remove_dir_all(&app_dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn reproducible_timestamps_survive_other_time_zones() {
+        let _time_zone = lock_time_zone();
+        let app_dir = std::env::temp_dir().join("onex_reproducible_timestamps_time_zones");
+        fs::create_dir_all(&app_dir).unwrap();
+        fs::write(app_dir.join("a.txt"), b"a").unwrap();
+
+        let options = ArchiveOptions {
+            reproducible_timestamp: Some(1_600_000_000),
+            ..Default::default()
+        };
+        let bytes = write_app_dir(&app_dir, None, &options, SeekableVec::new(Vec::new()))
+            .unwrap()
+            .into_vec();
+
+        let old_tz = env::var_os("TZ");
+        env::set_var("TZ", "Asia/Kolkata");
+        let entries = list_entries(SeekableVec::new(bytes));
+        match old_tz {
+            Some(tz) => env::set_var("TZ", tz),
+            None => env::remove_var("TZ"),
+        }
+        assert_eq!(entries.unwrap()[0].modified, 1_600_000_000);
+
+        fs::remove_dir_all(&app_dir).unwrap();
+    }
+
+    fn zip_with_names(names: &[&str]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(SeekableVec::new(Vec::new()));
+        for name in names {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(name.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_vec()
+    }
+
+    #[test]
+    fn relative_entry_paths() {
+        let reject = UnsafePathPolicy::Reject;
+        let sanitize = UnsafePathPolicy::Sanitize;
+        assert_eq!(
+            relative_entry_path("a/./b\\c", reject).unwrap(),
+            Some(["a", "b", "c"].iter().collect())
+        );
+
+        for name in &["/etc/passwd", "\\evil", "a/../../b", "..\\..\\b"] {
+            let err = relative_entry_path(name, reject).err().unwrap();
+            assert!(format!("{}", err).starts_with(&format!("Refusing to extract '{}'", name)));
+        }
+
+        assert_eq!(
+            relative_entry_path("/a/../b", sanitize).unwrap(),
+            Some(["a", "b"].iter().collect())
+        );
+        assert_eq!(relative_entry_path("../..", sanitize).unwrap(), None);
+
+        let colons = relative_entry_path("a:b/file.txt:stream", reject);
+        if cfg!(windows) {
+            for name in &["C:/b", "c:b", "a/file.txt:stream"] {
+                assert!(relative_entry_path(name, reject).is_err());
+            }
+            assert_eq!(
+                relative_entry_path("C:\\b", sanitize).unwrap(),
+                Some(PathBuf::from("b"))
+            );
+            assert!(colons.is_err());
+        } else {
+            assert_eq!(
+                colons.unwrap(),
+                Some(["a:b", "file.txt:stream"].iter().collect())
+            );
+        }
+    }
+
+    #[test]
+    fn extract_zip_rejects_traversal() {
+        let temp_dir = std::env::temp_dir().join("onex_extract_zip_rejects_traversal");
+        let output_dir = temp_dir.join("out");
+        let bytes = zip_with_names(&["ok.txt", "../escaped.txt"]);
+
+        let result = extract_zip(
+            SeekableVec::new(bytes.clone()),
+            &output_dir,
+            UnsafePathPolicy::Reject,
+        );
+        assert!(matches!(result, Err(crate::Error::UnsafePath(_))));
+        assert!(!output_dir.join("ok.txt").exists());
+
+        extract_zip(
+            SeekableVec::new(bytes),
+            &output_dir,
+            UnsafePathPolicy::Sanitize,
+        )
+        .unwrap();
+        assert!(output_dir.join("ok.txt").exists());
+        assert!(output_dir.join("escaped.txt").exists());
+        assert!(!temp_dir.join("escaped.txt").exists());
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_zip_allows_colons_in_names() {
+        let output_dir = std::env::temp_dir().join("onex_extract_zip_allows_colons_in_names");
+        let bytes = zip_with_names(&["a:b.txt", "dir/12:00.log"]);
+
+        extract_zip(
+            SeekableVec::new(bytes),
+            &output_dir,
+            UnsafePathPolicy::Reject,
+        )
+        .unwrap();
+        assert!(output_dir.join("a:b.txt").is_file());
+        assert!(output_dir.join("dir").join("12:00.log").is_file());
+
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_zip_rejects_symlink_escapes() {
+        let temp_dir = std::env::temp_dir().join("onex_extract_zip_rejects_symlink_escapes");
+        let output_dir = temp_dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        std::os::unix::fs::symlink(&temp_dir, output_dir.join("link")).unwrap();
+
+        let result = extract_zip(
+            SeekableVec::new(zip_with_names(&["link/escaped.txt"])),
+            &output_dir,
+            UnsafePathPolicy::Sanitize,
+        );
+        assert!(matches!(result, Err(crate::Error::UnsafePath(_))));
+        assert!(!temp_dir.join("escaped.txt").exists());
+
+        // Each of these stays inside on its own, but together they lead out.
+        let mut zip = ZipWriter::new(SeekableVec::new(Vec::new()));
+        zip.add_symlink("a/l1", ".", FileOptions::default())
+            .unwrap();
+        zip.add_symlink("l2", "a/l1/../..", FileOptions::default())
+            .unwrap();
+        let bytes = zip.finish().unwrap().into_vec();
+        let chained_dir = temp_dir.join("chained");
+        let result = extract_zip(
+            SeekableVec::new(bytes),
+            &chained_dir,
+            UnsafePathPolicy::Reject,
+        );
+        assert!(matches!(result, Err(crate::Error::UnsafePath(_))));
+        assert!(!chained_dir.join("l2").exists());
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn app_dir_entries_filters() {
+        let app_dir = std::env::temp_dir().join("onex_app_dir_entries_filters");
+        fs::create_dir_all(app_dir.join(".git")).unwrap();
+        fs::create_dir_all(app_dir.join("assets")).unwrap();
+        fs::write(app_dir.join(".git").join("HEAD"), b"").unwrap();
+        fs::write(app_dir.join("assets").join("logo.png"), b"").unwrap();
+        fs::write(app_dir.join("app.exe"), b"").unwrap();
+        fs::write(app_dir.join("app.pdb"), b"").unwrap();
+        fs::write(app_dir.join("debug.log"), b"").unwrap();
+        fs::write(app_dir.join(IGNORE_FILE), b".git/\n*.pdb\n").unwrap();
+
+        let names = |options: &ArchiveOptions| {
+            app_dir_entries(&app_dir, options)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect::<Vec<_>>()
+        };
+
+        let mut options = ArchiveOptions::default();
+        assert_eq!(
+            names(&options),
+            vec!["app.exe", "assets", "assets/logo.png", "debug.log"]
+        );
+
+        options.exclude = vec!["*.log".to_owned()];
+        assert_eq!(
+            names(&options),
+            vec!["app.exe", "assets", "assets/logo.png"]
+        );
+
+        options.include = vec!["assets/".to_owned()];
+        assert_eq!(names(&options), vec!["assets", "assets/logo.png"]);
+        assert!(write_app_dir(
+            &app_dir,
+            Some(Path::new("app.exe")),
+            &options,
+            SeekableVec::new(Vec::new())
+        )
+        .is_err());
+
+        fs::remove_dir_all(&app_dir).unwrap();
+    }
+
+    #[test]
+    fn symlinks_must_stay_inside() {
+        let no_symlinks = |_: &Path| false;
+        let stays_inside =
+            |path: &str, target: &str| symlink_stays_inside(Path::new(path), target, &no_symlinks);
+        assert!(stays_inside("lib/libfoo.so", "libfoo.so.1"));
+        assert!(stays_inside("lib/current", "../bin/./app"));
+        assert!(!stays_inside("lib/up", "../../etc"));
+        assert!(!stays_inside("link", "/etc/passwd"));
+        assert_eq!(stays_inside("link", "C:\\Windows"), !cfg!(windows));
+
+        let lib_current = |path: &Path| path == Path::new("lib/current");
+        assert!(symlink_stays_inside(
+            Path::new("bin/app"),
+            "../lib/current/app",
+            &lib_current
+        ));
+        assert!(!symlink_stays_inside(
+            Path::new("bin/app"),
+            "../lib/current/../app",
+            &lib_current
+        ));
+        assert!(!symlink_stays_inside(
+            Path::new("lib/current/up"),
+            "../app",
+            &lib_current
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn modes_and_symlinks_round_trip() {
+        let temp_dir = std::env::temp_dir().join("onex_modes_and_symlinks_round_trip");
+        let app_dir = temp_dir.join("app");
+        fs::create_dir_all(app_dir.join("lib")).unwrap();
+        fs::write(app_dir.join("run.sh"), b"#!/bin/sh").unwrap();
+        fs::set_permissions(app_dir.join("run.sh"), fs::Permissions::from_mode(0o750)).unwrap();
+        fs::write(app_dir.join("lib").join("libfoo.so.1"), b"foo").unwrap();
+        symlink("libfoo.so.1", app_dir.join("lib").join("libfoo.so")).unwrap();
+
+        let bytes = write_app_dir(
+            &app_dir,
+            None,
+            &ArchiveOptions::default(),
+            SeekableVec::new(Vec::new()),
+        )
+        .unwrap()
+        .into_vec();
+        let output_dir = temp_dir.join("out");
+        extract_zip(
+            SeekableVec::new(bytes),
+            &output_dir,
+            UnsafePathPolicy::Reject,
+        )
+        .unwrap();
+
+        let mode = fs::metadata(output_dir.join("run.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o750);
+        let link = output_dir.join("lib").join("libfoo.so");
+        assert_eq!(fs::read_link(&link).unwrap(), PathBuf::from("libfoo.so.1"));
+        assert_eq!(fs::read(&link).unwrap(), b"foo");
+
+        let options = ArchiveOptions {
+            dereference_symlinks: true,
+            ..Default::default()
+        };
+        let entries = app_dir_entries(&app_dir, &options).unwrap();
+        assert!(!entries
+            .iter()
+            .any(|entry| matches!(entry.kind, AppDirEntryKind::Symlink(_))));
+
+        let mut zip = ZipWriter::new(SeekableVec::new(Vec::new()));
+        zip.add_symlink("escape", "../..", FileOptions::default())
+            .unwrap();
+        let bytes = zip.finish().unwrap().into_vec();
+        let result = extract_zip(
+            SeekableVec::new(bytes),
+            &temp_dir.join("escape"),
+            UnsafePathPolicy::Reject,
+        );
+        assert!(matches!(result, Err(crate::Error::UnsafePath(_))));
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn civil_times_round_trip() {
+        for timestamp in &[ZIP_EPOCH, 951_782_400, 1_600_000_000, ZIP_END] {
+            let date_time = zip_date_time(*timestamp).unwrap();
+            assert_eq!(timestamp_from_civil(&date_time), *timestamp);
+        }
+        assert_eq!(format_timestamp(1_600_000_001), "2020-09-13 12:26:41");
+        assert_eq!(format_timestamp(-1), "1969-12-31 23:59:59");
+        assert_eq!(
+            extended_timestamp(&[0x55, 0x54, 5, 0, 1, 1, 0, 0, 0]),
+            Some(1)
+        );
+        assert_eq!(extended_timestamp(&[0x55, 0x54, 1, 0, 0]), None);
+        assert_eq!(extended_timestamp(&[0x55, 0x54, 5, 0, 1]), None);
+    }
+
+    #[test]
+    fn modification_times_round_trip() {
+        let _time_zone = lock_time_zone();
+        let temp_dir = std::env::temp_dir().join("onex_modification_times_round_trip");
+        let _ = fs::remove_dir_all(&temp_dir);
+        let app_dir = temp_dir.join("app");
+        fs::create_dir_all(app_dir.join("d")).unwrap();
+        fs::write(app_dir.join("d").join("b.txt"), b"b").unwrap();
+        fs::write(app_dir.join("old.txt"), b"old").unwrap();
+        let set_mtime = |path: &Path, timestamp| {
+            filetime::set_file_mtime(path, FileTime::from_unix_time(timestamp, 0)).unwrap()
+        };
+        set_mtime(&app_dir.join("d").join("b.txt"), 1_600_000_001);
+        set_mtime(&app_dir.join("d"), 1_500_000_000);
+        set_mtime(&app_dir.join("old.txt"), 100_000_000);
+
+        let mtime = |path: &Path| {
+            FileTime::from_last_modification_time(&path.metadata().unwrap()).unix_seconds()
+        };
+        for &extended_timestamps in &[false, true] {
+            let options = ArchiveOptions {
+                extended_timestamps,
+                ..Default::default()
+            };
+            let bytes = write_app_dir(&app_dir, None, &options, SeekableVec::new(Vec::new()))
+                .unwrap()
+                .into_vec();
+            let output_dir = temp_dir.join(format!("output_{}", extended_timestamps));
+            extract_zip(
+                SeekableVec::new(bytes),
+                &output_dir,
+                UnsafePathPolicy::Reject,
+            )
+            .unwrap();
+
+            // Without extended timestamps, times are rounded down to even
+            // seconds and clamped to the start of 1980 in local time.
+            let (b, old) = if extended_timestamps {
+                (1_600_000_001, 100_000_000)
+            } else {
+                let zip_epoch = timestamp_from_local_civil(&zip_date_time(ZIP_EPOCH).unwrap());
+                (1_600_000_000, zip_epoch)
+            };
+            assert_eq!(mtime(&output_dir.join("d").join("b.txt")), b);
+            assert_eq!(mtime(&output_dir.join("old.txt")), old);
+            assert_eq!(mtime(&output_dir.join("d")), 1_500_000_000);
+        }
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn list_entries_describes_entries() {
+        let _time_zone = lock_time_zone();
+        let mut zip = ZipWriter::new(SeekableVec::new(Vec::new()));
+        zip.add_directory("d/", FileOptions::default().unix_permissions(0o755))
+            .unwrap();
+        zip.start_file(
+            "d/a.txt",
+            FileOptions::default()
+                .compression_method(CompressionMethod::Deflated)
+                .last_modified_time(local_zip_date_time(1_600_000_000).unwrap())
+                .unix_permissions(0o600),
+        )
+        .unwrap();
+        zip.write_all(b"aaaaaaaaaaaaaaaa").unwrap();
+        zip.add_symlink("link", "d/a.txt", FileOptions::default())
+            .unwrap();
+        let bytes = zip.finish().unwrap().into_vec();
+
+        let entries = list_entries(SeekableVec::new(bytes)).unwrap();
+        let summary = entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.kind.clone(), entry.mode))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("d/", ArchiveEntryKind::Directory, Some(0o755)),
+                ("d/a.txt", ArchiveEntryKind::File, Some(0o600)),
+                (
+                    "link",
+                    ArchiveEntryKind::Symlink("d/a.txt".to_owned()),
+                    Some(0o777)
+                ),
+            ]
+        );
+
+        let file = &entries[1];
+        assert_eq!(file.size, 16);
+        assert!(file.compressed_size < file.size);
+        assert_eq!(file.compression, CompressionMethod::Deflated);
+        assert_eq!(file.crc32, 0xcfd6_68d5);
+        assert_eq!(file.modified, 1_600_000_000);
+    }
+
+    #[test]
+    fn extract_entries_selects_and_strips() {
+        let temp_dir = std::env::temp_dir().join("onex_extract_entries_selects_and_strips");
+        let _ = fs::remove_dir_all(&temp_dir);
+        let bytes = zip_with_names(&["app/a.txt", "app/conf/x.toml", "app/conf/y.json", "b.txt"]);
+        let extract = |output_dir: &Path, options: &ExtractOptions| {
+            extract_entries(SeekableVec::new(bytes.clone()), output_dir, options)
+        };
+        let files_in = |dir: &Path| {
+            let mut files = WalkDir::new(dir)
+                .into_iter()
+                .map(|entry| entry.unwrap())
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| {
+                    let path = entry.path().strip_prefix(dir).unwrap();
+                    path.to_string_lossy().replace('\\', "/")
+                })
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+
+        let output_dir = temp_dir.join("conf");
+        let options = ExtractOptions {
+            patterns: vec!["app/conf".to_owned(), "*.txt".to_owned()],
+            strip_components: 1,
+            ..Default::default()
+        };
+        extract(&output_dir, &options).unwrap();
+        assert_eq!(
+            files_in(&output_dir),
+            vec!["a.txt", "conf/x.toml", "conf/y.json"]
+        );
+
+        let output_dir = temp_dir.join("toml");
+        let options = ExtractOptions {
+            patterns: vec!["*.toml".to_owned()],
+            ..Default::default()
+        };
+        extract(&output_dir, &options).unwrap();
+        assert_eq!(files_in(&output_dir), vec!["app/conf/x.toml"]);
+
+        let options = ExtractOptions {
+            patterns: vec!["*.exe".to_owned()],
+            ..Default::default()
+        };
+        assert!(extract(&output_dir, &options).is_err());
+
+        fs::write(output_dir.join("app").join("conf").join("x.toml"), b"mine").unwrap();
+        let x = output_dir.join("app").join("conf").join("x.toml");
+        let mut options = ExtractOptions {
+            existing_files: ExistingFilePolicy::Fail,
+            ..Default::default()
+        };
+        assert!(extract(&output_dir, &options).is_err());
+        assert!(!output_dir.join("b.txt").exists());
+
+        options.existing_files = ExistingFilePolicy::Skip;
+        extract(&output_dir, &options).unwrap();
+        assert_eq!(fs::read(&x).unwrap(), b"mine");
+        assert!(output_dir.join("b.txt").exists());
+
+        options.existing_files = ExistingFilePolicy::Overwrite;
+        extract(&output_dir, &options).unwrap();
+        assert_eq!(fs::read(&x).unwrap(), b"app/conf/x.toml");
+
+        fs::remove_dir_all(&temp_dir).unwrap();
+    }
+
+    #[test]
+    fn open_entry_reads_one_file() {
+        let bytes = zip_with_names(&["a.txt", "d/", "d/b.txt"]);
+        let mut archive = ZipArchive::new(SeekableVec::new(bytes)).unwrap();
+
+        for path in &["d/b.txt", "d\\b.txt", "./d//b.txt"] {
+            let mut contents = String::new();
+            open_entry(&mut archive, path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(contents, "d/b.txt");
+        }
+        assert!(open_entry(&mut archive, "d").is_err());
+        assert!(open_entry(&mut archive, "c.txt").is_err());
+    }
 }
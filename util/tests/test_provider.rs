@@ -1,20 +1,23 @@
+#[cfg(windows)]
 use {
     std::{env, error, io, path::PathBuf},
     util::{zip_app_dir, ProjfsProvider, ReadSeek, SeekableVec},
     zip::ZipArchive,
 };
 
+#[cfg(windows)]
 fn enable_logging() {
     flexi_logger::Logger::with_str("trace").start().unwrap();
 }
 
+#[cfg(windows)]
 fn main() -> Result<(), Box<dyn error::Error>> {
     enable_logging();
 
     let mut args = env::args();
     let root = args.nth(1).unwrap();
 
-    let zip_bytes = zip_app_dir(&PathBuf::from("../testapp/assets")).unwrap();
+    let zip_bytes = zip_app_dir(&PathBuf::from("../testapp/assets"), None).unwrap();
     let seeker = SeekableVec::new(zip_bytes);
 
     let seeker: Box<dyn ReadSeek> = Box::new(seeker);
@@ -29,3 +32,8 @@ fn main() -> Result<(), Box<dyn error::Error>> {
 
     Ok(())
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("test_provider needs ProjFS, which only exists on Windows.");
+}